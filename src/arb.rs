@@ -1,50 +1,460 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
-use crate::utils::stringe;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, value::RawValue};
+
+use crate::{icu, journal::Journal, project::NewKeyPosition, utils::stringe};
 
 #[derive(Debug, Clone)]
 pub struct ArbFile {
     pub path: PathBuf,
+    journal: Journal,
+    new_keys: NewKeyPosition,
+}
+
+/// The content of an arb file: the messages, their `@key` metadata, and the `@@` global
+/// attributes like `@@locale` and `@@last_modified`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArbDocument {
+    /// By name, without the `@@`.
+    pub globals: BTreeMap<String, Value>,
+    pub messages: BTreeMap<String, String>,
+    /// By message key, without the `@`. Metadata of keys with no message is kept as well.
+    pub metadata: BTreeMap<String, Metadata>,
+    /// The json keys in the order they were read, so writing the file back does not
+    /// shuffle it around.
+    order: Vec<String>,
+    style: Style,
+    /// The file content this was parsed from, to tell if it changed on disk since.
+    source: Option<String>,
+    /// The json text of each entry as it was read, written back as is while its value
+    /// stays the same, so escapes and key order inside `@key` entries survive.
+    raw: BTreeMap<String, String>,
+}
+
+/// A value of the written json, `Verbatim` for entries left as they were read.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Entry<'a> {
+    Verbatim(&'a RawValue),
+    Value(Value),
+}
+
+/// How the file was formatted, to write it back the same way.
+#[derive(Debug, Clone, PartialEq)]
+struct Style {
+    indent: String,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            indent: String::from("  "),
+            crlf: false,
+            trailing_newline: false,
+        }
+    }
+}
+
+impl Style {
+    fn detect(content: &str) -> Self {
+        let indent = content
+            .lines()
+            .skip(1)
+            .map(|l| &l[..l.len() - l.trim_start().len()])
+            .find(|i| !i.is_empty())
+            .unwrap_or("  ");
+        Self {
+            indent: indent.to_string(),
+            crlf: content.contains("\r\n"),
+            trailing_newline: content.ends_with('\n'),
+        }
+    }
+}
+
+/// What an `@key` entry holds, anything else in it is kept in `other`.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// In getter argument order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "ordered")]
+    pub placeholders: Vec<(String, Placeholder)>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Placeholder {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub example: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optional_parameters: Option<Value>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Placeholders as a json object, keeping their order.
+mod ordered {
+    use super::Placeholder;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error, ser::SerializeMap};
+    use serde_json::{Map, Value};
+
+    pub fn serialize<S: Serializer>(
+        placeholders: &[(String, Placeholder)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(placeholders.len()))?;
+        for (name, placeholder) in placeholders {
+            map.serialize_entry(name, placeholder)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(String, Placeholder)>, D::Error> {
+        Map::<String, Value>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(name, value)| {
+                serde_json::from_value(value)
+                    .map(|p| (name.clone(), p))
+                    .map_err(|e| D::Error::custom(format!("placeholder `{name}`: {e}")))
+            })
+            .collect()
+    }
+}
+
+impl ArbDocument {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let json: Map<String, Value> = stringe("invalid arb json", serde_json::from_str(content))?;
+        let raw: BTreeMap<String, &RawValue> =
+            stringe("invalid arb json", serde_json::from_str(content))?;
+        let mut document = Self {
+            order: json.keys().cloned().collect(),
+            style: Style::detect(content),
+            source: Some(content.to_string()),
+            raw: raw
+                .into_iter()
+                .map(|(key, value)| (key, value.get().to_string()))
+                .collect(),
+            ..Self::default()
+        };
+        for (key, value) in json {
+            if let Some(name) = key.strip_prefix("@@") {
+                document.globals.insert(name.to_string(), value);
+            } else if let Some(name) = key.strip_prefix('@') {
+                let metadata = stringe(
+                    format!("invalid metadata `{key}`").as_str(),
+                    serde_json::from_value(value),
+                )?;
+                document.metadata.insert(name.to_string(), metadata);
+            } else {
+                match value {
+                    Value::String(message) => {
+                        document.messages.insert(key, message);
+                    }
+                    _ => return Err(format!("the message `{key}` is not a string")),
+                }
+            }
+        }
+        Ok(document)
+    }
+
+    /// The json keys in the order to write them: as they were read, new messages going at
+    /// `new_keys` with their metadata right after them, and new globals after the others.
+    fn write_order(&self, new_keys: NewKeyPosition) -> Vec<String> {
+        let present = |raw: &String| match raw.strip_prefix("@@") {
+            Some(name) => self.globals.contains_key(name),
+            None => match raw.strip_prefix('@') {
+                Some(key) => self.metadata.contains_key(key),
+                None => self.messages.contains_key(raw),
+            },
+        };
+        let mut order: Vec<String> = self.order.iter().filter(|k| present(k)).cloned().collect();
+        let mut seen: BTreeSet<String> = order.iter().cloned().collect();
+
+        for name in self.globals.keys() {
+            let raw = format!("@@{name}");
+            if seen.insert(raw.clone()) {
+                let at = order
+                    .iter()
+                    .rposition(|k| k.starts_with("@@"))
+                    .map_or(0, |i| i + 1);
+                order.insert(at, raw);
+            }
+        }
+        for key in self.messages.keys() {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            let metadata = format!("@{key}");
+            let at = match order.iter().position(|k| *k == metadata) {
+                // metadata already there, the message goes just before it
+                Some(i) => i,
+                None => match new_keys {
+                    NewKeyPosition::End => order.len(),
+                    NewKeyPosition::Sorted => {
+                        let at = order
+                            .iter()
+                            .position(|k| !k.starts_with('@') && k > key)
+                            .unwrap_or(order.len());
+                        // files sorted the BTreeMap way have `@key` before `key`
+                        match at.checked_sub(1) {
+                            Some(i) if order[i] == format!("@{}", order[at]) => i,
+                            _ => at,
+                        }
+                    }
+                },
+            };
+            order.insert(at, key.clone());
+        }
+        for key in self.metadata.keys() {
+            let raw = format!("@{key}");
+            if seen.insert(raw.clone()) {
+                let at = order
+                    .iter()
+                    .position(|k| k == key)
+                    .map_or(order.len(), |i| i + 1);
+                order.insert(at, raw);
+            }
+        }
+        order
+    }
+
+    /// The json text `raw` was read with, if it still holds `value`.
+    fn verbatim<T: DeserializeOwned + PartialEq>(&self, raw: &str, value: &T) -> Option<&RawValue> {
+        let text = self.raw.get(raw)?;
+        (serde_json::from_str::<T>(text).ok().as_ref() == Some(value))
+            .then(|| serde_json::from_str(text).ok())
+            .flatten()
+    }
+
+    /// The arb json, in the order and with the indentation and line endings it was read with.
+    /// Entries which did not change are written back exactly as they were read.
+    pub fn to_json(&self, new_keys: NewKeyPosition) -> Result<String, String> {
+        let mut json = Vec::new();
+        for raw in self.write_order(new_keys) {
+            let entry = match raw.strip_prefix("@@") {
+                Some(name) => match self.verbatim(&raw, &self.globals[name]) {
+                    Some(text) => Entry::Verbatim(text),
+                    None => Entry::Value(self.globals[name].clone()),
+                },
+                None => match raw.strip_prefix('@') {
+                    Some(key) => match self.verbatim(&raw, &self.metadata[key]) {
+                        Some(text) => Entry::Verbatim(text),
+                        None => Entry::Value(stringe(
+                            "could not serialize metadata",
+                            serde_json::to_value(&self.metadata[key]),
+                        )?),
+                    },
+                    None => match self.verbatim(&raw, &self.messages[&raw]) {
+                        Some(text) => Entry::Verbatim(text),
+                        None => Entry::Value(Value::String(self.messages[&raw].clone())),
+                    },
+                },
+            };
+            json.push((raw, entry));
+        }
+
+        let mut out = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(self.style.indent.as_bytes());
+        let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+        stringe(
+            "could not serialize arb file",
+            serde::Serializer::collect_map(&mut serializer, json),
+        )?;
+        let mut out = String::from_utf8(out).unwrap();
+        if self.style.trailing_newline {
+            out.push('\n');
+        }
+        if self.style.crlf {
+            out = out.replace('\n', "\r\n");
+        }
+        Ok(out)
+    }
+
+    /// Renames `old` to `new`, metadata included, keeping their place in the file.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let (message, metadata) = self.remove(old);
+        let metadata_key = format!("@{old}");
+        for raw in &mut self.order {
+            if raw == old {
+                *raw = new.to_string();
+            } else if *raw == metadata_key {
+                *raw = format!("@{new}");
+            }
+        }
+        self.messages.extend(message.map(|m| (new.to_string(), m)));
+        self.metadata.extend(metadata.map(|m| (new.to_string(), m)));
+    }
+
+    pub fn locale(&self) -> Option<&str> {
+        self.globals.get("locale").and_then(Value::as_str)
+    }
+
+    /// The ICU syntax tree of the message of `key`.
+    pub fn parse_message(
+        &self,
+        key: &str,
+        escaping: bool,
+    ) -> Option<Result<icu::Message, icu::ParseError>> {
+        self.messages.get(key).map(|m| icu::parse(m, escaping))
+    }
+
+    /// The placeholders of `key` in getter argument order: those of the `@key` metadata,
+    /// then the other arguments of the message in order of appearance.
+    pub fn placeholder_order(&self, key: &str, escaping: bool) -> Vec<String> {
+        let mut order: Vec<String> = self
+            .metadata
+            .get(key)
+            .map(|m| {
+                m.placeholders
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if let Some(Ok(message)) = self.parse_message(key, escaping) {
+            for name in icu::arguments(&message) {
+                if !order.contains(&name) {
+                    order.push(name);
+                }
+            }
+        }
+        order
+    }
+
+    /// Removes `key` and its metadata, returning them.
+    pub fn remove(&mut self, key: &str) -> (Option<String>, Option<Metadata>) {
+        (self.messages.remove(key), self.metadata.remove(key))
+    }
 }
 
 impl ArbFile {
-    pub fn new(path: PathBuf) -> Self {
-        Self { path }
+    pub fn new(path: PathBuf, journal: Journal, new_keys: NewKeyPosition) -> Self {
+        Self {
+            path,
+            journal,
+            new_keys,
+        }
     }
-    pub fn read(&self) -> Result<BTreeMap<String, serde_json::Value>, String> {
+    pub fn read(&self) -> Result<ArbDocument, String> {
         let content = stringe(
             format!("could not read arb file content at {0:?}", self.path).as_str(),
             std::fs::read_to_string(&self.path),
         )?;
-        stringe(
-            format!(
-                "could not decode main arb file json: {0:?} of {1:?} ",
-                content, self.path
-            )
-            .as_str(),
-            serde_json::from_str(content.as_str()),
-        )
-    }
-    pub fn write(&self, json: &BTreeMap<String, serde_json::Value>) -> Result<(), String> {
-        let new_data = stringe(
-            "could not serialize modified arb file back to json ",
-            serde_json::to_string_pretty(json),
-        )?;
-        if let Err(e) = stringe(
-            "could not write json content to arb file",
-            std::fs::write(&self.path, new_data),
-        ) {
-            Err(e)
-        } else {
+        ArbDocument::parse(&content).map_err(|e| format!("{e} in {0:?}", self.path))
+    }
+    /// Writes `document` back, unless the file changed on disk since it was read.
+    pub fn write(&self, document: &ArbDocument) -> Result<(), String> {
+        let new_data = document.to_json(self.new_keys)?;
+        if self
+            .journal
+            .replace(&self.path, document.source.as_deref(), &new_data)?
+        {
             Ok(())
+        } else {
+            Err(format!(
+                "{} changed on disk since it was read, not overwriting it",
+                self.path.display()
+            ))
+        }
+    }
+    /// Reads the file, lets `f` modify it and writes it back when `f` returns true. When the
+    /// file changes on disk in the meantime, it is read again and `f` applied to that.
+    pub fn update(
+        &self,
+        mut f: impl FnMut(&mut ArbDocument) -> Result<bool, String>,
+    ) -> Result<bool, String> {
+        for _ in 0..5 {
+            let mut document = self.read()?;
+            if !f(&mut document)? {
+                return Ok(false);
+            }
+            let new_data = document.to_json(self.new_keys)?;
+            if self
+                .journal
+                .replace(&self.path, document.source.as_deref(), &new_data)?
+            {
+                return Ok(true);
+            }
         }
+        Err(format!(
+            "{} keeps changing on disk, could not update it",
+            self.path.display()
+        ))
+    }
+    /// Removes `keys` along with their `@key` metadata, returns how many entries went away.
+    pub fn remove_keys(&self, keys: &BTreeSet<String>) -> Result<usize, String> {
+        let mut removed = 0;
+        self.update(|arb| {
+            removed = keys
+                .iter()
+                .map(|key| match arb.remove(key) {
+                    (Some(_), Some(_)) => 2,
+                    (None, None) => 0,
+                    _ => 1,
+                })
+                .sum();
+            Ok(removed > 0)
+        })?;
+        Ok(removed)
     }
-    pub fn add_key(&self, key: &str, value: &str) -> Result<(), String> {
-        let mut arb = self.read()?;
-        arb.insert(
-            key.to_string(),
-            serde_json::Value::String(value.to_string()),
-        );
-        self.write(&arb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = r#"{
+    "@@locale": "fr",
+    "cafe": "Caf\u00e9",
+    "@cafe": {
+        "placeholders": {},
+        "description": "Le caf\u00e9"
+    },
+    "hello": "Bonjour {name}",
+    "@hello": {
+        "placeholders": {
+            "name": {
+                "example": "Ana",
+                "type": "String"
+            }
+        },
+        "description": "greeting"
+    }
+}
+"#;
+
+    #[test]
+    fn untouched_entries_are_written_verbatim() {
+        let mut document = ArbDocument::parse(CONTENT).unwrap();
+        assert_eq!(document.to_json(NewKeyPosition::End).unwrap(), CONTENT);
+
+        document.metadata.get_mut("hello").unwrap().description = Some("salut".into());
+        document.messages.insert("bye".into(), "Au revoir".into());
+        let json = document.to_json(NewKeyPosition::End).unwrap();
+        // the changed entry is written from its fields, the others as they were
+        assert!(json.contains("    \"cafe\": \"Caf\\u00e9\",\n"), "{json}");
+        assert!(json.contains("\"description\": \"Le caf\\u00e9\"\n    },"));
+        assert!(json.contains(
+            "    \"@hello\": {\n        \"description\": \"salut\",\n        \"placeholders\""
+        ));
+        assert!(json.ends_with("    \"bye\": \"Au revoir\"\n}\n"));
     }
 }
@@ -0,0 +1,147 @@
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use crate::dart::{self, TokenKind};
+use crate::project::Project;
+
+/// A reference to a localized message in dart code, as written by the extractor
+/// from the call-site templates: `AppLocalizations.of(context)!.key(a, b)`.
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// The whole call, arguments included.
+    pub span: Range<usize>,
+    pub key: String,
+    pub key_span: Range<usize>,
+    /// The dart source of each argument.
+    pub args: Vec<String>,
+}
+
+/// Turns a call-site template into a regex, `{key}` being captured and `{args}` left out
+/// since the arguments are read with the dart lexer. Spaces may appear around punctuation,
+/// as `dart format` likes to add them.
+fn template_regex(template: &str, class: &str) -> Regex {
+    let template = template.replace("{class}", class).replace("{args}", "");
+    let mut pattern = String::new();
+    let mut previous: Option<char> = None;
+    let mut rest = template.as_str();
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{key}") {
+            pattern.push_str("(?P<key>[A-Za-z_$][A-Za-z0-9_$]*)");
+            previous = Some('k');
+            rest = after;
+            continue;
+        }
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+        if previous.is_some_and(|p| !(word(p) && word(c))) {
+            pattern.push_str(r"\s*");
+        }
+        pattern.push_str(&regex::escape(&c.to_string()));
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Regex::new(&pattern).unwrap()
+}
+
+/// Finds the call sites in `content` matching the project's call-site templates,
+/// skipping matches inside comments and strings.
+pub fn find_call_sites(p: &Project, content: &str) -> Result<Vec<CallSite>, String> {
+    let tokens = dart::tokenize(content)?;
+    let mut templates = vec![p.call_site_template()];
+    templates.extend(p.config.context_free_call_site.clone());
+
+    let mut sites = Vec::new();
+    for template in templates {
+        let with_args = template.contains("{args}");
+        let regex = template_regex(&template, &p.output_class);
+        for cap in regex.captures_iter(content) {
+            let full = cap.get(0).unwrap();
+            let key = cap.name("key").unwrap();
+            let i = tokens.partition_point(|t| t.span.end <= full.start());
+            // in a comment or a string, or in the middle of a longer identifier
+            let misplaced = tokens
+                .get(i)
+                .is_some_and(|t| t.span.start < full.start() || t.is_comment())
+                || tokens
+                    .get(i.wrapping_sub(1))
+                    .is_some_and(|t| t.span.end == full.start() && t.kind == TokenKind::Ident);
+            if misplaced {
+                continue;
+            }
+            let mut site = CallSite {
+                span: full.range(),
+                key: key.as_str().to_string(),
+                key_span: key.range(),
+                args: Vec::new(),
+            };
+            if with_args {
+                read_args(content, &tokens, &mut site);
+            }
+            if !sites
+                .iter()
+                .any(|s: &CallSite| s.span.start == site.span.start)
+            {
+                sites.push(site);
+            }
+        }
+    }
+    sites.sort_by_key(|s| s.span.start);
+    Ok(sites)
+}
+
+/// Every key referenced by a call site in the project's dart files, fails when a file
+/// can't be read since its keys would look unused.
+pub fn referenced_keys(p: &Project) -> Result<BTreeSet<String>, String> {
+    let mut keys = BTreeSet::new();
+    for file in p.dart_files() {
+        let display = file.strip_prefix(&p.root_dir).unwrap_or(&file).display();
+        let content =
+            std::fs::read_to_string(&file).map_err(|e| format!("could not read {display}: {e}"))?;
+        let sites = find_call_sites(p, &content).map_err(|e| format!("{display}: {e}"))?;
+        keys.extend(sites.into_iter().map(|s| s.key));
+    }
+    Ok(keys)
+}
+
+/// Reads the `(a, b)` following a call site, when there is one, into `site.args`.
+fn read_args(content: &str, tokens: &[dart::Token], site: &mut CallSite) {
+    let code: Vec<&dart::Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut i = code.partition_point(|t| t.span.start < site.span.end);
+    if !code
+        .get(i)
+        .is_some_and(|t| t.is_punct(content, "(") && t.span.start == site.span.end)
+    {
+        return;
+    }
+    let mut depth = 0;
+    let mut arg_start = code[i].span.end;
+    while let Some(t) = code.get(i) {
+        if t.kind == TokenKind::Punct {
+            match t.text(content) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let arg = content[arg_start..t.span.start].trim();
+                        if !arg.is_empty() {
+                            site.args.push(arg.to_string());
+                        }
+                        site.span.end = t.span.end;
+                        return;
+                    }
+                }
+                "," if depth == 1 => {
+                    site.args
+                        .push(content[arg_start..t.span.start].trim().to_string());
+                    arg_start = t.span.end;
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::arb::ArbDocument;
+use crate::dart;
+use crate::diagnostic::{self, Diagnostic, Format, Severity};
+use crate::icu::{self, Case, Message, Node};
+use crate::project::Project;
+use crate::utils::stringe;
+
+/// The CLDR cardinal plural categories, by locale or language, `other` left out as the ICU
+/// parser already requires it. Languages missing here are not checked.
+const PLURAL_CATEGORIES: &[(&[&str], &[&str])] = &[
+    (
+        &[
+            "bo", "dz", "id", "ig", "ii", "ja", "jv", "kde", "kea", "km", "ko", "lo", "ms", "my",
+            "sah", "ses", "sg", "su", "th", "to", "vi", "wo", "yo", "yue", "zh",
+        ],
+        &[],
+    ),
+    (
+        &[
+            "af", "ak", "am", "as", "ast", "az", "bg", "bn", "ce", "ckb", "da", "de", "ee", "el",
+            "en", "eo", "et", "eu", "fa", "fi", "fil", "fo", "fur", "fy", "gl", "gu", "ha", "haw",
+            "hi", "hu", "hy", "ia", "is", "ka", "kk", "kl", "kn", "ks", "ku", "ky", "lb", "lg",
+            "ln", "mg", "mk", "ml", "mn", "mr", "nb", "nd", "ne", "nl", "nn", "no", "ny", "om",
+            "or", "os", "pa", "ps", "rm", "sd", "si", "sn", "so", "sq", "st", "sv", "sw", "ta",
+            "te", "ti", "tk", "tl", "tn", "tr", "ts", "ug", "ur", "uz", "ve", "xh", "yi", "zu",
+        ],
+        &["one"],
+    ),
+    (
+        &["ca", "es", "fr", "it", "pt", "pt_PT", "vec"],
+        &["one", "many"],
+    ),
+    (&["lv", "prg", "ksh", "lag"], &["zero", "one"]),
+    (
+        &["he", "iw", "iu", "naq", "se", "sma", "smj", "smn", "sms"],
+        &["one", "two"],
+    ),
+    (&["bs", "hr", "sh", "sr", "ro", "mo"], &["one", "few"]),
+    (
+        &["be", "cs", "lt", "pl", "ru", "sk", "uk"],
+        &["one", "few", "many"],
+    ),
+    (&["dsb", "gd", "hsb", "sl"], &["one", "two", "few"]),
+    (&["br", "ga", "gv", "mt"], &["one", "two", "few", "many"]),
+    (
+        &["ar", "ars", "cy", "kw"],
+        &["zero", "one", "two", "few", "many"],
+    ),
+];
+
+/// The plural categories a `plural` message needs in `locale`, `pt_BR` falls back to `pt`.
+fn plural_categories(locale: &str) -> Option<&'static [&'static str]> {
+    let locale = locale.replace('-', "_");
+    let language = locale.split('_').next().unwrap_or_default().to_lowercase();
+    [locale.as_str(), language.as_str()].iter().find_map(|l| {
+        PLURAL_CATEGORIES
+            .iter()
+            .find(|(locales, _)| locales.contains(l))
+            .map(|(_, categories)| *categories)
+    })
+}
+
+/// The `plural` choices of `message`, nested ones included, `selectordinal` left out.
+fn plurals<'a>(message: &'a Message, out: &mut Vec<(&'a str, &'a [Case])>) {
+    for node in message {
+        let cases = match node {
+            Node::Plural {
+                name,
+                ordinal,
+                cases,
+                ..
+            } => {
+                if !ordinal {
+                    out.push((name, cases));
+                }
+                cases
+            }
+            Node::Select { cases, .. } => cases,
+            Node::Text(_) | Node::Argument { .. } | Node::Pound => continue,
+        };
+        for case in cases {
+            plurals(&case.message, out);
+        }
+    }
+}
+
+/// The top level keys of the arb json with their offsets, duplicates included where serde
+/// keeps only the last one. `content` must be valid json.
+fn key_offsets(content: &str) -> Vec<(String, usize)> {
+    let mut keys = Vec::new();
+    let mut depth = 0;
+    let mut expect_key = false;
+    let mut chars = content.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' | '[' => {
+                depth += 1;
+                expect_key = depth == 1;
+            }
+            '}' | ']' => depth -= 1,
+            ',' => expect_key = depth == 1,
+            '"' => {
+                let mut end = content.len();
+                while let Some((j, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => {
+                            end = j + 1;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if expect_key {
+                    keys.push((
+                        serde_json::from_str(&content[i..end]).unwrap_or_default(),
+                        i,
+                    ));
+                    expect_key = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// The locale of an arb file by its name, flutter's way: `my_app_pt_BR.arb` is `pt_BR`. It is
+/// read from the end, an optional region and script before them, then the language.
+fn file_locale(path: &Path) -> Option<&str> {
+    let stem = path.file_stem()?.to_str()?;
+    let mut prefix = stem;
+    let mut strip = |matches: fn(&str) -> bool| match prefix.rsplit_once('_') {
+        Some((before, segment)) if matches(segment) => {
+            prefix = before;
+            true
+        }
+        _ => false,
+    };
+    strip(|s| {
+        (s.len() == 2 && s.chars().all(|c| c.is_ascii_uppercase()))
+            || (s.len() == 3 && s.chars().all(|c| c.is_ascii_digit()))
+    });
+    strip(|s| {
+        s.len() == 4
+            && s.starts_with(|c: char| c.is_ascii_uppercase())
+            && s[1..].chars().all(|c| c.is_ascii_lowercase())
+    });
+    if !strip(|s| (2..=3).contains(&s.len()) && s.chars().all(|c| c.is_ascii_lowercase())) {
+        return None;
+    }
+    Some(&stem[prefix.len() + 1..])
+}
+
+/// What is wrong with the arb file at `path`. `template` is `None` for the template itself.
+fn check_file(
+    p: &Project,
+    path: &Path,
+    template: Option<&ArbDocument>,
+) -> Result<Vec<Diagnostic>, String> {
+    let content = stringe(
+        format!("could not read {}", path.display()).as_str(),
+        std::fs::read_to_string(path),
+    )?;
+    let diagnostic = |severity, code, message: String| {
+        Diagnostic::new(severity, code, &p.root_dir, path, message)
+    };
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(&content) {
+        let d = diagnostic(
+            Severity::Error,
+            "invalid-json",
+            format!("invalid arb json: {e}"),
+        );
+        return Ok(vec![d.at((e.line(), e.column()))]);
+    }
+    let document = match ArbDocument::parse(&content) {
+        Ok(document) => document,
+        Err(e) => return Ok(vec![diagnostic(Severity::Error, "invalid-arb", e)]),
+    };
+
+    let keys = key_offsets(&content);
+    // where the diagnostics about a key point to, its first occurrence
+    let position = |raw: &str| {
+        keys.iter()
+            .find(|(k, _)| k == raw)
+            .map_or((1, 1), |(_, offset)| dart::line_col(&content, *offset))
+    };
+    let mut diagnostics = Vec::new();
+
+    let mut seen = BTreeSet::new();
+    for (key, offset) in &keys {
+        if !seen.insert(key) {
+            diagnostics.push(
+                diagnostic(
+                    Severity::Error,
+                    "duplicate-key",
+                    format!("{key}: appears more than once, only the last one counts"),
+                )
+                .at(dart::line_col(&content, *offset)),
+            );
+        }
+    }
+    let from_name = file_locale(path);
+    if let (Some(locale), Some(from_name)) = (document.locale(), from_name)
+        && locale.replace('-', "_") != from_name
+    {
+        diagnostics.push(
+            diagnostic(
+                Severity::Error,
+                "locale-mismatch",
+                format!("`@@locale` is `{locale}` but the file name says `{from_name}`"),
+            )
+            .at(position("@@locale")),
+        );
+    }
+    for key in document.metadata.keys() {
+        if !document.messages.contains_key(key) {
+            let raw = format!("@{key}");
+            diagnostics.push(
+                diagnostic(
+                    Severity::Warning,
+                    "orphan-metadata",
+                    format!("{raw}: metadata of no message"),
+                )
+                .at(position(&raw)),
+            );
+        }
+    }
+
+    let categories = document.locale().or(from_name).and_then(plural_categories);
+    for (key, text) in &document.messages {
+        let at = position(key);
+        let message = match icu::parse(text, p.use_escaping) {
+            Ok(message) => message,
+            Err(e) => {
+                diagnostics.push(
+                    diagnostic(
+                        Severity::Error,
+                        "invalid-icu",
+                        format!("{key}: invalid ICU message: {e}"),
+                    )
+                    .at(at),
+                );
+                continue;
+            }
+        };
+        // a `#` message is the template's one waiting for the translator
+        if text.starts_with('#') {
+            continue;
+        }
+        if let Some(Ok(expected)) = template.and_then(|t| t.parse_message(key, p.use_escaping)) {
+            let expected: BTreeSet<String> = icu::arguments(&expected).into_iter().collect();
+            let arguments: BTreeSet<String> = icu::arguments(&message).into_iter().collect();
+            let missing: Vec<_> = expected.difference(&arguments).collect();
+            let extra: Vec<_> = arguments.difference(&expected).collect();
+            if !missing.is_empty() {
+                diagnostics.push(
+                    diagnostic(
+                        Severity::Error,
+                        "missing-placeholder",
+                        format!("{key}: missing placeholders {missing:?}"),
+                    )
+                    .at(at),
+                );
+            }
+            if !extra.is_empty() {
+                diagnostics.push(
+                    diagnostic(
+                        Severity::Error,
+                        "extra-placeholder",
+                        format!("{key}: placeholders {extra:?} are not in the template"),
+                    )
+                    .at(at),
+                );
+            }
+        }
+        let Some(categories) = categories else {
+            continue;
+        };
+        let mut choices = Vec::new();
+        plurals(&message, &mut choices);
+        for (name, cases) in choices {
+            // `=1` is no `one`, which also covers 21, 31... in many locales
+            let missing: Vec<&str> = categories
+                .iter()
+                .copied()
+                .filter(|c| !cases.iter().any(|case| case.selector == *c))
+                .collect();
+            if !missing.is_empty() {
+                diagnostics.push(
+                    diagnostic(
+                        Severity::Warning,
+                        "missing-plural-case",
+                        format!("{key}: the plural `{name}` lacks the {missing:?} cases"),
+                    )
+                    .at(at),
+                );
+            }
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// `arb-util check`, validates every arb file against the template: placeholders, ICU syntax,
+/// plural cases for the locale, `@@locale`, duplicate keys and orphan metadata. Returns
+/// whether problems were found.
+pub fn check(p: &Project, format: Format) -> Result<bool, String> {
+    let template_path = p.arb_template_path();
+    let template = p.arb_file(template_path.clone()).read()?;
+    let mut diagnostics = Vec::new();
+    for path in p.arb_files()? {
+        let is_template = path == template_path;
+        diagnostics.extend(check_file(p, &path, (!is_template).then_some(&template))?);
+    }
+    diagnostic::report(format, "check", &diagnostics);
+    if format.is_text() {
+        if diagnostics.is_empty() {
+            println!("[check] Every arb file is valid");
+        } else {
+            println!("[check] {} problems", diagnostics.len());
+        }
+    }
+    Ok(!diagnostics.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_locales() {
+        let locale = |name: &str| file_locale(Path::new(name)).map(str::to_string);
+        assert_eq!(locale("app_en.arb").as_deref(), Some("en"));
+        assert_eq!(locale("my_app_ru.arb").as_deref(), Some("ru"));
+        assert_eq!(locale("app_pt_BR.arb").as_deref(), Some("pt_BR"));
+        assert_eq!(
+            locale("my_app_zh_Hant_TW.arb").as_deref(),
+            Some("zh_Hant_TW")
+        );
+        assert_eq!(locale("intl_es_419.arb").as_deref(), Some("es_419"));
+        assert_eq!(locale("strings.arb"), None);
+        assert_eq!(locale("app_US.arb"), None);
+    }
+}
@@ -0,0 +1,1042 @@
+use std::ops::Range;
+
+/// A small Dart lexer, it knows just enough of dart to tell string literals, comments
+/// identifiers and punctuation apart, and where exactly each of them sits in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident,
+    Number,
+    Str(DartString),
+    Comment,
+    Punct,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+impl Token {
+    pub fn text<'a>(&self, src: &'a str) -> &'a str {
+        &src[self.span.clone()]
+    }
+    pub fn is_punct(&self, src: &str, p: &str) -> bool {
+        self.kind == TokenKind::Punct && self.text(src) == p
+    }
+    pub fn is_ident(&self, src: &str, name: &str) -> bool {
+        self.kind == TokenKind::Ident && self.text(src) == name
+    }
+    pub fn is_comment(&self) -> bool {
+        self.kind == TokenKind::Comment
+    }
+}
+
+/// A decoded string literal, escapes resolved and interpolations kept apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DartString {
+    pub raw: bool,
+    pub triple: bool,
+    pub parts: Vec<StringPart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Text(String),
+    /// `$name` or `${expr}`, `expr` is the trimmed dart source of the expression.
+    Interpolation {
+        expr: String,
+        span: Range<usize>,
+    },
+}
+
+/// Returns the 1-based line and column of the byte `offset` in `src`.
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, col)
+}
+
+/// The comments attached to `span`: after it on the same line, or alone on the line above.
+pub fn attached_comments<'t>(
+    src: &str,
+    tokens: &'t [Token],
+    span: &Range<usize>,
+) -> Vec<&'t Token> {
+    let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[span.end..]
+        .find('\n')
+        .map_or(src.len(), |i| span.end + i);
+    let previous_line_start = src[..line_start.saturating_sub(1)]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    tokens
+        .iter()
+        .filter(|t| t.is_comment())
+        .filter(|t| {
+            let after = t.span.start >= span.end && t.span.start < line_end;
+            let above = t.span.end < line_start
+                && t.span.start >= previous_line_start
+                && src[previous_line_start..t.span.start].trim().is_empty();
+            after || above
+        })
+        .collect()
+}
+
+/// The bracket structure of a file, comments left out.
+pub struct Nesting<'t> {
+    pub code: Vec<&'t Token>,
+    /// The index of the matching bracket, for brackets.
+    pub matching: Vec<Option<usize>>,
+    /// The index of the innermost opening bracket around each token.
+    pub parent: Vec<Option<usize>>,
+}
+
+impl<'t> Nesting<'t> {
+    pub fn new(src: &str, tokens: &'t [Token]) -> Self {
+        let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+        let mut matching = vec![None; code.len()];
+        let mut parent = vec![None; code.len()];
+        let mut stack: Vec<usize> = Vec::new();
+        for (i, t) in code.iter().enumerate() {
+            if t.kind != TokenKind::Punct {
+                parent[i] = stack.last().copied();
+                continue;
+            }
+            match t.text(src) {
+                "(" | "[" | "{" => {
+                    parent[i] = stack.last().copied();
+                    stack.push(i);
+                }
+                ")" | "]" | "}" => {
+                    if let Some(open) = stack.pop() {
+                        matching[open] = Some(i);
+                        matching[i] = Some(open);
+                    }
+                    parent[i] = stack.last().copied();
+                }
+                _ => parent[i] = stack.last().copied(),
+            }
+        }
+        Self {
+            code,
+            matching,
+            parent,
+        }
+    }
+
+    /// The index of the first token starting at or after `offset`.
+    pub fn index_at(&self, offset: usize) -> usize {
+        self.code.partition_point(|t| t.span.start < offset)
+    }
+
+    /// The opening brackets around `offset`, innermost first.
+    pub fn enclosing(&self, offset: usize) -> Vec<usize> {
+        let i = self.index_at(offset);
+        let mut chain = Vec::new();
+        let mut current = match self.code.get(i) {
+            Some(_) => self.parent[i],
+            None => None,
+        };
+        // `offset` may sit right on a bracket, which then is not its own parent
+        if let Some(t) = self.code.get(i)
+            && t.span.start == offset
+            && self.matching[i].is_some_and(|m| m > i)
+        {
+            current = self.parent[i];
+        }
+        while let Some(open) = current {
+            chain.push(open);
+            current = self.parent[open];
+        }
+        chain
+    }
+
+    /// The end of the expression following `=>` at index `arrow`, exclusive.
+    fn arrow_body_end(&self, src: &str, arrow: usize) -> usize {
+        let mut i = arrow + 1;
+        while let Some(t) = self.code.get(i) {
+            if t.kind == TokenKind::Punct {
+                match t.text(src) {
+                    ";" | "," | ")" | "]" | "}" => return i,
+                    "(" | "[" | "{" => {
+                        i = self.matching[i].unwrap_or(self.code.len());
+                    }
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        self.code.len()
+    }
+
+    /// The functions around `offset`, outermost first: their parameter lists as
+    /// `(open, close)` token indices and the index of their body's `{` or `=>`.
+    fn functions_around(&self, src: &str, offset: usize) -> Vec<(usize, usize, usize)> {
+        let params_before = |i: usize| {
+            // skip `async`, `async*` and `sync*`
+            let mut j = i.checked_sub(1)?;
+            while self.code[j].is_ident(src, "async")
+                || self.code[j].is_ident(src, "sync")
+                || self.code[j].is_punct(src, "*")
+            {
+                j = j.checked_sub(1)?;
+            }
+            let close = self.code[j];
+            if !close.is_punct(src, ")") {
+                return None;
+            }
+            Some((self.matching[j]?, j))
+        };
+        let mut functions: Vec<(usize, usize, usize)> = self
+            .enclosing(offset)
+            .into_iter()
+            .filter(|&open| self.code[open].is_punct(src, "{"))
+            .filter_map(|open| params_before(open).map(|(o, c)| (o, c, open)))
+            .collect();
+        let at = self.index_at(offset);
+        for arrow in 1..at.min(self.code.len()) {
+            let is_arrow = self.code[arrow - 1].is_punct(src, "=")
+                && self.code[arrow].is_punct(src, ">")
+                && self.code[arrow - 1].span.end == self.code[arrow].span.start;
+            if is_arrow
+                && self.arrow_body_end(src, arrow) > at
+                && let Some((open, close)) = params_before(arrow - 1)
+            {
+                functions.push((open, close, arrow));
+            }
+        }
+        functions.sort_by_key(|f| f.2);
+        functions
+    }
+
+    /// The class whose body `{` is at `open`, if it is one: the index of its name.
+    fn class_at(&self, src: &str, open: usize) -> Option<usize> {
+        let mut j = open.checked_sub(1)?;
+        loop {
+            let t = self.code[j];
+            if self.parent[j] != self.parent[open] {
+                return None;
+            }
+            if t.is_ident(src, "class") || t.is_ident(src, "mixin") {
+                return Some(j + 1).filter(|&n| n < open);
+            }
+            let header =
+                t.kind == TokenKind::Ident || matches!(t.text(src), "<" | ">" | "," | "." | "?");
+            if !header {
+                return None;
+            }
+            j = j.checked_sub(1)?;
+        }
+    }
+}
+
+/// Tells if a `context` variable is usable at `offset`: a `context` parameter of an
+/// enclosing function or closure, or a method (but `initState`, `dispose` and constructors)
+/// of a `State` subclass or of a class with a `context` field or getter.
+pub fn context_in_scope(src: &str, tokens: &[Token], offset: usize) -> bool {
+    let nesting = Nesting::new(src, tokens);
+    let functions = nesting.functions_around(src, offset);
+    let has_context_param = functions.iter().any(|&(open, close, _)| {
+        nesting.code[open + 1..close]
+            .iter()
+            .any(|t| t.is_ident(src, "context"))
+    });
+    if has_context_param {
+        return true;
+    }
+
+    let Some((body, name)) = nesting
+        .enclosing(offset)
+        .into_iter()
+        .find_map(|open| Some((open, nesting.class_at(src, open)?)))
+    else {
+        return false;
+    };
+    let class_name = nesting.code[name].text(src);
+    let header = &nesting.code[name..body];
+    let is_state = header.windows(2).any(|w| {
+        w[0].kind == TokenKind::Ident
+            && w[0].text(src).ends_with("State")
+            && w[1].is_punct(src, "<")
+    });
+    let end = nesting.matching[body].unwrap_or(nesting.code.len());
+    let has_context_member = (body + 1..end)
+        .filter(|&i| nesting.parent[i] == Some(body))
+        .any(|i| {
+            nesting.code[i].is_ident(src, "context")
+                && (nesting.code[i - 1].is_ident(src, "BuildContext")
+                    || nesting.code[i - 1].is_ident(src, "get"))
+        });
+    if !is_state && !has_context_member {
+        return false;
+    }
+
+    // the method right in the class body, field initializers have no context
+    let Some(&(open, _, _)) = functions
+        .iter()
+        .find(|&&(open, _, _)| nesting.parent[open] == Some(body))
+    else {
+        return false;
+    };
+    let method = open.checked_sub(1).map(|i| nesting.code[i].text(src));
+    let constructor = method == Some(class_name)
+        || (open >= 3
+            && nesting.code[open - 3].is_ident(src, class_name)
+            && nesting.code[open - 2].is_punct(src, "."));
+    !constructor && !matches!(method, Some("initState" | "dispose"))
+}
+
+/// Identifiers which, right before another one, start an expression and not a declaration.
+const NOT_TYPES: &[&str] = &[
+    "as", "await", "case", "else", "in", "is", "new", "return", "throw", "yield",
+];
+
+/// The type of the declaration of `name` visible at `offset`, the innermost one: a local,
+/// a parameter of an enclosing function, a field of an enclosing class or a top-level
+/// variable. With `member`, for `widget.name`, the fields of every class count as well.
+/// None when there is no such declaration or its type is not a plain name, `final name`
+/// or `List<int> name`.
+pub fn declared_type(
+    src: &str,
+    tokens: &[Token],
+    offset: usize,
+    name: &str,
+    member: bool,
+) -> Option<String> {
+    let nesting = Nesting::new(src, tokens);
+    let code = &nesting.code;
+    // the scopes seen from `offset`, innermost first, by the index they start at: the
+    // enclosing brackets and the parameter lists of the enclosing functions
+    let mut scopes: Vec<(usize, usize)> = nesting
+        .enclosing(offset)
+        .into_iter()
+        .map(|open| (open, open))
+        .collect();
+    scopes.extend(
+        nesting
+            .functions_around(src, offset)
+            .into_iter()
+            .map(|(open, _, body)| (body, open)),
+    );
+    scopes.sort_by_key(|&scope| std::cmp::Reverse(scope));
+    let rank = |scope: Option<usize>| match scope {
+        None => Some(scopes.len()),
+        Some(open) => scopes.iter().position(|&(_, o)| o == open).or_else(|| {
+            (member && nesting.class_at(src, open).is_some()).then_some(scopes.len() + 1)
+        }),
+    };
+
+    let mut best: Option<(usize, Option<String>)> = None;
+    for i in 1..code.len() {
+        if !code[i].is_ident(src, name) {
+            continue;
+        }
+        let declares = code.get(i + 1).is_some_and(|next| {
+            matches!(next.text(src), "=" | ";" | "," | ")" | "}" | "]") || next.is_ident(src, "in")
+        });
+        let previous = code[i - 1];
+        let kind = match previous.kind {
+            _ if !declares => continue,
+            TokenKind::Ident if NOT_TYPES.contains(&previous.text(src)) => continue,
+            TokenKind::Ident => Some(previous.text(src).to_string()),
+            // `int? name`
+            _ if previous.is_punct(src, "?") && i >= 2 && code[i - 2].kind == TokenKind::Ident => {
+                Some(code[i - 2].text(src).to_string())
+            }
+            // `List<int> name`
+            _ if previous.is_punct(src, ">") => None,
+            _ => continue,
+        };
+        let mut scope = nesting.parent[i];
+        // named and optional parameters, `({int count})`, belong to the parameter list
+        if let Some(open) = scope
+            && matches!(code[open].text(src), "{" | "[")
+            && open > 0
+            && matches!(code[open - 1].text(src), "(" | ",")
+        {
+            scope = nesting.parent[open];
+        }
+        if let Some(rank) = rank(scope)
+            && best.as_ref().is_none_or(|(r, _)| rank < *r)
+        {
+            best = Some((rank, kind));
+        }
+    }
+    best.and_then(|(_, kind)| kind)
+        .filter(|k| !matches!(k.as_str(), "final" | "var" | "const" | "late"))
+}
+
+/// Finds the `const` keywords a non constant expression at `offset` would break: those of
+/// the enclosing `const Text(...)`, `const [...]` and `const {...}`, which are to be removed,
+/// and that of an enclosing `const x = ...` declaration, which is to become `final`.
+pub fn invalidated_consts(
+    src: &str,
+    tokens: &[Token],
+    offset: usize,
+) -> Vec<(Range<usize>, &'static str)> {
+    let nesting = Nesting::new(src, tokens);
+    let code = &nesting.code;
+    let is_const = |i: usize| code[i].is_ident(src, "const");
+    // with the spaces following it
+    let const_span = |i: usize| {
+        let end = code[i].span.end;
+        code[i].span.start
+            ..end + src[end..].len() - src[end..].trim_start_matches([' ', '\t']).len()
+    };
+    // skips `<...>` type arguments backwards from `i`, returns the index before them
+    let skip_type_args = |mut i: usize| -> Option<usize> {
+        if !code[i].is_punct(src, ">") {
+            return Some(i);
+        }
+        let mut depth = 0;
+        loop {
+            if code[i].is_punct(src, ">") {
+                depth += 1;
+            } else if code[i].is_punct(src, "<") {
+                depth -= 1;
+                if depth == 0 {
+                    return i.checked_sub(1);
+                }
+            }
+            i = i.checked_sub(1)?;
+        }
+    };
+
+    let mut edits = Vec::new();
+    let mut top = nesting.index_at(offset);
+    for open in nesting.enclosing(offset) {
+        let Some(before) = open.checked_sub(1).and_then(skip_type_args) else {
+            break;
+        };
+        match code[open].text(src) {
+            "(" => {
+                // `const Foo<T>.named(`
+                let mut j = before;
+                while code[j].kind == TokenKind::Ident && !is_const(j) {
+                    match j.checked_sub(1).and_then(skip_type_args) {
+                        Some(k) if code[k].is_punct(src, ".") && k > 0 => {
+                            match skip_type_args(k - 1) {
+                                Some(k) => j = k,
+                                None => break,
+                            }
+                        }
+                        Some(k) => {
+                            j = k;
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+                if is_const(j) {
+                    edits.push((const_span(j), ""));
+                }
+            }
+            "[" | "{" => {
+                let literal = code[open].text(src) == "["
+                    || matches!(
+                        code[before].text(src),
+                        "=" | "(" | "," | ":" | "[" | "?" | ">" | "return" | "const"
+                    );
+                if !literal {
+                    // a block, the expression ends here
+                    break;
+                }
+                if is_const(before) {
+                    edits.push((const_span(before), ""));
+                }
+            }
+            _ => {}
+        }
+        top = open;
+    }
+
+    // `const x = ...;` or `static const Widget x = ...;`
+    let mut seen_equals = false;
+    let mut j = top;
+    while let Some(k) = j.checked_sub(1) {
+        if nesting.parent[k] != nesting.parent[top] || matches!(code[k].text(src), ";" | "{" | "}")
+        {
+            break;
+        }
+        if code[k].is_punct(src, "=") {
+            seen_equals = true;
+        } else if seen_equals && is_const(k) {
+            edits.push((code[k].span.clone(), "final"));
+            break;
+        }
+        j = k;
+    }
+    edits
+}
+
+/// A `library`, `import`, `export`, `part` or `part of` directive at the top of a file,
+/// `target` is the uri, or the library name for `library` and `part of name;`.
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub keyword: &'static str,
+    pub target: String,
+    pub span: Range<usize>,
+}
+
+/// Reads the directives at the top of the file, up to the first declaration.
+pub fn directives(src: &str, tokens: &[Token]) -> Vec<Directive> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut directives = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        // annotations, as in `@TestOn('vm') library;`
+        if code[i].is_punct(src, "@") {
+            i += 2;
+            while i + 1 < code.len() && code[i].is_punct(src, ".") {
+                i += 2;
+            }
+            if code.get(i).is_some_and(|t| t.is_punct(src, "(")) {
+                let mut depth = 0;
+                while let Some(t) = code.get(i) {
+                    i += 1;
+                    match t.text(src) {
+                        "(" if t.kind == TokenKind::Punct => depth += 1,
+                        ")" if t.kind == TokenKind::Punct => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            continue;
+        }
+        let keyword = match code[i].text(src) {
+            "library" => "library",
+            "import" => "import",
+            "export" => "export",
+            "part" if code.get(i + 1).is_some_and(|t| t.is_ident(src, "of")) => "part of",
+            "part" => "part",
+            _ => break,
+        };
+        if code[i].kind != TokenKind::Ident {
+            break;
+        }
+        let start = i;
+        while i < code.len() && !code[i].is_punct(src, ";") {
+            i += 1;
+        }
+        let Some(end) = code.get(i) else {
+            break;
+        };
+        let first = start + if keyword == "part of" { 2 } else { 1 };
+        let target = match code.get(first).map(|t| &t.kind) {
+            Some(TokenKind::Str(s)) => s
+                .parts
+                .iter()
+                .map(|p| match p {
+                    StringPart::Text(t) => t.as_str(),
+                    StringPart::Interpolation { .. } => "",
+                })
+                .collect(),
+            _ => code[first.min(i)..i].iter().map(|t| t.text(src)).collect(),
+        };
+        directives.push(Directive {
+            keyword,
+            target,
+            span: code[start].span.start..end.span.end,
+        });
+        i += 1;
+    }
+    directives
+}
+
+/// Finds the name of the innermost class, mixin, enum or extension whose body contains `offset`.
+pub fn enclosing_class(src: &str, tokens: &[Token], offset: usize) -> Option<String> {
+    let mut scopes: Vec<Option<&str>> = Vec::new();
+    let mut pending = None;
+    let mut code = tokens.iter().filter(|t| !t.is_comment()).peekable();
+    while let Some(token) = code.next() {
+        if token.span.start >= offset {
+            break;
+        }
+        match (&token.kind, token.text(src)) {
+            (TokenKind::Ident, "class" | "mixin" | "enum" | "extension") => {
+                pending = code
+                    .peek()
+                    .filter(|t| t.kind == TokenKind::Ident && !t.is_ident(src, "on"))
+                    .map(|t| t.text(src));
+            }
+            (TokenKind::Punct, "{") => scopes.push(pending.take()),
+            (TokenKind::Punct, "}") => {
+                scopes.pop();
+            }
+            (TokenKind::Punct, ";") => pending = None,
+            _ => {}
+        }
+    }
+    scopes.into_iter().rev().flatten().next().map(String::from)
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut lexer = Lexer { src, pos: 0 };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+    fn peek_at(&self, n: usize) -> Option<u8> {
+        self.src.as_bytes().get(self.pos + n).copied()
+    }
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+    fn error(&self, at: usize, msg: &str) -> String {
+        let (line, col) = line_col(self.src, at);
+        format!("{msg} at {line}:{col}")
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, String> {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += self.peek().unwrap().len_utf8();
+        }
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+
+        let kind = if self.rest().starts_with("//") {
+            self.pos = self
+                .rest()
+                .find('\n')
+                .map_or(self.src.len(), |i| self.pos + i);
+            TokenKind::Comment
+        } else if self.rest().starts_with("/*") {
+            self.block_comment(start)?;
+            TokenKind::Comment
+        } else if c == 'r' && matches!(self.peek_at(1), Some(b'\'' | b'"')) {
+            self.pos += 1;
+            TokenKind::Str(self.string(true)?)
+        } else if c == '\'' || c == '"' {
+            TokenKind::Str(self.string(false)?)
+        } else if is_ident_start(c) {
+            self.pos += self.ident_len(true);
+            TokenKind::Ident
+        } else if c.is_ascii_digit()
+            || (c == '.' && self.peek_at(1).is_some_and(|b| b.is_ascii_digit()))
+        {
+            self.number(start);
+            TokenKind::Number
+        } else {
+            self.pos += c.len_utf8();
+            TokenKind::Punct
+        };
+        Ok(Some(Token {
+            kind,
+            span: start..self.pos,
+        }))
+    }
+
+    fn ident_len(&self, dollar: bool) -> usize {
+        self.rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || (dollar && c == '$')))
+            .unwrap_or(self.rest().len())
+    }
+
+    fn number(&mut self, start: usize) {
+        let hex = self.src[start..].starts_with("0x") || self.src[start..].starts_with("0X");
+        // good enough: hex, exponents and separators all end up in the same token
+        while let Some(c) = self.peek() {
+            let exponent_sign = (c == '+' || c == '-')
+                && matches!(self.src.as_bytes()[self.pos - 1], b'e' | b'E')
+                && !hex;
+            let decimal_dot = c == '.' && self.peek_at(1).is_some_and(|b| b.is_ascii_digit());
+            if c.is_ascii_alphanumeric() || c == '_' || decimal_dot || exponent_sign {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn block_comment(&mut self, start: usize) -> Result<(), String> {
+        // dart block comments nest
+        let mut depth = 0;
+        loop {
+            if self.rest().starts_with("/*") {
+                depth += 1;
+                self.pos += 2;
+            } else if self.rest().starts_with("*/") {
+                depth -= 1;
+                self.pos += 2;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if let Some(c) = self.peek() {
+                self.pos += c.len_utf8();
+            } else {
+                return Err(self.error(start, "unterminated block comment"));
+            }
+        }
+    }
+
+    /// Lexes a string literal, `self.pos` must be on the opening quote.
+    fn string(&mut self, raw: bool) -> Result<DartString, String> {
+        let start = self.pos;
+        let quote = self.peek().unwrap();
+        let triple_quote = quote.to_string().repeat(3);
+        let triple = self.rest().starts_with(&triple_quote);
+        let closing = if triple {
+            triple_quote
+        } else {
+            quote.to_string()
+        };
+        self.pos += closing.len();
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error(start, "unterminated string literal"));
+            };
+            if self.rest().starts_with(&closing) {
+                self.pos += closing.len();
+                break;
+            }
+            if c == '\n' && !triple {
+                return Err(self.error(start, "unterminated string literal"));
+            }
+            if c == '\\' && !raw {
+                self.pos += 1;
+                text.push(self.escape(start)?);
+                continue;
+            }
+            if c == '$'
+                && !raw
+                && let Some(part) = self.interpolation()?
+            {
+                if !text.is_empty() {
+                    parts.push(StringPart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(part);
+                continue;
+            }
+            text.push(c);
+            self.pos += c.len_utf8();
+        }
+        if !text.is_empty() {
+            parts.push(StringPart::Text(text));
+        }
+        Ok(DartString { raw, triple, parts })
+    }
+
+    fn escape(&mut self, start: usize) -> Result<char, String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error(start, "unterminated string literal"));
+        };
+        self.pos += c.len_utf8();
+        let hex = |s: &str| u32::from_str_radix(s, 16).ok().and_then(char::from_u32);
+        Ok(match c {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'v' => '\u{b}',
+            'x' => {
+                let digits = self.rest().get(..2).unwrap_or("");
+                self.pos += digits.len();
+                hex(digits).ok_or_else(|| self.error(start, "invalid \\x escape"))?
+            }
+            'u' if self.rest().starts_with('{') => {
+                let end = self
+                    .rest()
+                    .find('}')
+                    .ok_or_else(|| self.error(start, "invalid \\u{} escape"))?;
+                let digits = &self.src[self.pos + 1..self.pos + end];
+                self.pos += end + 1;
+                hex(digits).ok_or_else(|| self.error(start, "invalid \\u{} escape"))?
+            }
+            'u' => {
+                let digits = self.rest().get(..4).unwrap_or("");
+                self.pos += digits.len();
+                hex(digits).ok_or_else(|| self.error(start, "invalid \\u escape"))?
+            }
+            other => other,
+        })
+    }
+
+    /// Lexes `$name` or `${expr}`, returns None when the `$` is just a dollar sign.
+    fn interpolation(&mut self) -> Result<Option<StringPart>, String> {
+        let start = self.pos;
+        if self.peek_at(1) == Some(b'{') {
+            self.pos += 2;
+            let mut depth = 0;
+            loop {
+                let Some(token) = self.next_token()? else {
+                    return Err(self.error(start, "unterminated string interpolation"));
+                };
+                if token.kind == TokenKind::Punct {
+                    match token.text(self.src) {
+                        "{" => depth += 1,
+                        "}" if depth == 0 => break,
+                        "}" => depth -= 1,
+                        _ => {}
+                    }
+                }
+            }
+            Ok(Some(StringPart::Interpolation {
+                expr: self.src[start + 2..self.pos - 1].trim().to_string(),
+                span: start..self.pos,
+            }))
+        } else if self.src[start + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            self.pos += 1;
+            self.pos += self.ident_len(false);
+            Ok(Some(StringPart::Interpolation {
+                expr: self.src[start + 1..self.pos].to_string(),
+                span: start..self.pos,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(src: &str) -> Vec<(Range<usize>, DartString)> {
+        tokenize(src)
+            .unwrap()
+            .into_iter()
+            .filter_map(|t| match t.kind {
+                TokenKind::Str(s) => Some((t.span, s)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn text(s: &str) -> Vec<StringPart> {
+        vec![StringPart::Text(s.to_string())]
+    }
+
+    #[test]
+    fn quotes_and_spans() {
+        let src = r#"f('single', "double");"#;
+        let found = strings(src);
+        assert_eq!(found.len(), 2);
+        assert_eq!(&src[found[0].0.clone()], "'single'");
+        assert_eq!(found[0].1.parts, text("single"));
+        assert_eq!(&src[found[1].0.clone()], "\"double\"");
+        assert_eq!(found[1].1.parts, text("double"));
+    }
+
+    #[test]
+    fn raw_strings_keep_backslashes_and_dollars() {
+        let found = strings(r#"r'a\n$b' r"c\${d}""#);
+        assert!(found.iter().all(|(_, s)| s.raw));
+        assert_eq!(found[0].1.parts, text(r"a\n$b"));
+        assert_eq!(found[1].1.parts, text(r"c\${d}"));
+    }
+
+    #[test]
+    fn triple_quoted_strings_span_lines() {
+        let src = "x = '''one\n'two'\n''' + \"\"\"3\"\"\";";
+        let found = strings(src);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].1.triple);
+        assert_eq!(found[0].1.parts, text("one\n'two'\n"));
+        assert_eq!(&src[found[1].0.clone()], "\"\"\"3\"\"\"");
+        assert!(tokenize("'no\nnewline'").is_err());
+    }
+
+    #[test]
+    fn adjacent_strings_are_separate_tokens() {
+        let src = "Text('a' \"b\"\n  r'c')";
+        let found = strings(src);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[1].0.start, src.find('"').unwrap());
+        assert_eq!(found[2].1.parts, text("c"));
+    }
+
+    #[test]
+    fn escapes() {
+        let found = strings(r#"'\n\t\'\\\$ \x41é\u{1F600}' "\"""#);
+        assert_eq!(found[0].1.parts, text("\n\t'\\$ A\u{e9}\u{1F600}"));
+        assert_eq!(found[1].1.parts, text("\""));
+        assert!(tokenize(r"'\u{zz}'").is_err());
+    }
+
+    #[test]
+    fn interpolations() {
+        let src = r#"'Hi $name, ${user.greet("x}")} costs $ 5 ${a[b{}]}'"#;
+        let found = strings(src);
+        assert_eq!(found.len(), 1);
+        let parts = &found[0].1.parts;
+        let exprs: Vec<&str> = parts
+            .iter()
+            .filter_map(|p| match p {
+                StringPart::Interpolation { expr, .. } => Some(expr.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(exprs, ["name", r#"user.greet("x}")"#, "a[b{}]"]);
+        assert_eq!(parts[2], StringPart::Text(", ".into()));
+        assert_eq!(parts[4], StringPart::Text(" costs $ 5 ".into()));
+        let StringPart::Interpolation { span, .. } = &parts[3] else {
+            panic!()
+        };
+        assert_eq!(&src[span.clone()], r#"${user.greet("x}")}"#);
+    }
+
+    #[test]
+    fn comments_hide_strings() {
+        let src = "// _('line')\n/* outer /* _('nested') */ still */ _('real')";
+        let found = strings(src);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.parts, text("real"));
+        let comments = tokenize(src)
+            .unwrap()
+            .into_iter()
+            .filter(Token::is_comment)
+            .count();
+        assert_eq!(comments, 2);
+        assert!(tokenize("/* /* */").is_err());
+    }
+
+    #[test]
+    fn declared_types_follow_scopes() {
+        let src = r#"
+int total = 0;
+void a(int name) {}
+Widget f(BuildContext context, String name) => Text('Hi $name $total');
+class Cart extends StatefulWidget {
+  final double price;
+  const Cart({super.key, required this.price});
+}
+class _CartState extends State<Cart> {
+  int? count;
+  Widget build(BuildContext context) {
+    final total = 'x';
+    return Text('$count ${widget.price} $total');
+  }
+  void g({int? name}) => print('$name');
+}
+"#;
+        let tokens = tokenize(src).unwrap();
+        let at = |needle: &str| src.find(needle).unwrap();
+        let hi = at("'Hi");
+        assert_eq!(
+            declared_type(src, &tokens, hi, "name", false),
+            Some("String".into())
+        );
+        assert_eq!(
+            declared_type(src, &tokens, hi, "total", false),
+            Some("int".into())
+        );
+        let build = at("'$count");
+        assert_eq!(
+            declared_type(src, &tokens, build, "count", false),
+            Some("int".into())
+        );
+        assert_eq!(declared_type(src, &tokens, build, "price", false), None);
+        assert_eq!(
+            declared_type(src, &tokens, build, "price", true),
+            Some("double".into())
+        );
+        assert_eq!(declared_type(src, &tokens, build, "total", false), None);
+        let g = at("'$name");
+        assert_eq!(
+            declared_type(src, &tokens, g, "name", false),
+            Some("int".into())
+        );
+    }
+
+    /// `src` with the consts a getter call at `marker` breaks removed or made final.
+    fn strip_consts(src: &str, marker: &str) -> String {
+        let tokens = tokenize(src).unwrap();
+        let mut edits = invalidated_consts(src, &tokens, src.find(marker).unwrap());
+        edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+        let mut out = src.to_string();
+        for (span, replacement) in edits {
+            out.replace_range(span, replacement);
+        }
+        out
+    }
+
+    #[test]
+    fn consts_around_a_call_are_removed() {
+        assert_eq!(
+            strip_consts(
+                "w = const Padding(padding: const EdgeInsets.all(8), child: Text(_('Hi')));",
+                "_(",
+            ),
+            "w = Padding(padding: const EdgeInsets.all(8), child: Text(_('Hi')));"
+        );
+        assert_eq!(
+            strip_consts(
+                "Column(children: const <Widget>[Text('a'), Text(_('b'))])",
+                "_(",
+            ),
+            "Column(children: <Widget>[Text('a'), Text(_('b'))])"
+        );
+        assert_eq!(
+            strip_consts("const Foo<int>.named(x: _('a'))", "_("),
+            "Foo<int>.named(x: _('a'))"
+        );
+        // declarations become final, the const of their value goes as well
+        assert_eq!(
+            strip_consts(
+                "class A {\n  static const Widget title = const Text(_('x'));\n}",
+                "_(",
+            ),
+            "class A {\n  static final Widget title = Text(_('x'));\n}"
+        );
+        assert_eq!(
+            strip_consts("const labels = {'a': _('b')};", "_("),
+            "final labels = {'a': _('b')};"
+        );
+    }
+
+    #[test]
+    fn const_stripping_stops_at_blocks() {
+        let src = r#"
+Widget build(BuildContext context) {
+  const gap = SizedBox(height: 8);
+  return const Outer(child: Builder(builder: (context) {
+    return Text(_('x'));
+  }));
+}"#;
+        assert_eq!(strip_consts(src, "_("), src);
+        let src = "void f() { const a = 1; g(const [_('x')]); }";
+        assert_eq!(
+            strip_consts(src, "_("),
+            "void f() { const a = 1; g([_('x')]); }"
+        );
+    }
+
+    #[test]
+    fn identifiers_and_numbers() {
+        let src = "final $x = 1.5e-3 + 0xFF;";
+        let tokens = tokenize(src).unwrap();
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text(src)).collect();
+        assert_eq!(texts, ["final", "$x", "=", "1.5e-3", "+", "0xFF", ";"]);
+        assert_eq!(tokens[3].kind, TokenKind::Number);
+        assert_eq!(line_col("a\nbé c", 6), (2, 4));
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A problem found in a dart or arb file, what `check`, `lint` and `extract` report.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What kind of problem it is, in kebab-case, the rule id in SARIF.
+    pub code: &'static str,
+    pub message: String,
+    /// Relative to the project root.
+    pub file: PathBuf,
+    /// 1-based, like the column.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl Diagnostic {
+    /// A diagnostic about the whole of `file`, `root` is stripped from it.
+    pub fn new(
+        severity: Severity,
+        code: &'static str,
+        root: &Path,
+        file: &Path,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            file: file.strip_prefix(root).unwrap_or(file).to_path_buf(),
+            line: None,
+            column: None,
+        }
+    }
+    pub fn at(mut self, (line, column): (usize, usize)) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+}
+
+/// `file:line:column: message`, the way the modes always printed them.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// How `check`, `lint` and `extract` print their diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// `[tag] file:line:column: message` lines, with the rest of the output
+    #[default]
+    Text,
+    /// One json object per line, and nothing else
+    Json,
+    /// A SARIF 2.1.0 log, as GitHub code scanning takes them, and nothing else
+    Sarif,
+}
+
+impl Format {
+    /// Whether the mode may print more than its diagnostics.
+    pub fn is_text(self) -> bool {
+        self == Format::Text
+    }
+}
+
+/// Prints `diagnostics` in `format`, `tag` prefixes the text lines.
+pub fn report(format: Format, tag: &str, diagnostics: &[Diagnostic]) {
+    match format {
+        Format::Text => {
+            for d in diagnostics {
+                println!("[{tag}] {d}");
+            }
+        }
+        Format::Json => {
+            for d in diagnostics {
+                println!("{}", serde_json::to_string(d).unwrap());
+            }
+        }
+        Format::Sarif => println!("{:#}", sarif(diagnostics)),
+    }
+}
+
+fn sarif(diagnostics: &[Diagnostic]) -> Value {
+    let rules: BTreeSet<&str> = diagnostics.iter().map(|d| d.code).collect();
+    let results: Vec<Value> = diagnostics
+        .iter()
+        .map(|d| {
+            let mut region = serde_json::Map::new();
+            if let Some(line) = d.line {
+                region.insert("startLine".into(), line.into());
+            }
+            if let Some(column) = d.column {
+                region.insert("startColumn".into(), column.into());
+            }
+            let mut location = json!({
+                "artifactLocation": {
+                    "uri": d.file.to_string_lossy().replace('\\', "/"),
+                    "uriBaseId": "%SRCROOT%",
+                },
+            });
+            if !region.is_empty() {
+                location["region"] = region.into();
+            }
+            json!({
+                "ruleId": d.code,
+                "level": d.severity,
+                "message": { "text": d.message },
+                "locations": [{ "physicalLocation": location }],
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "arb-util",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "results": results,
+        }],
+    })
+}
@@ -1,161 +1,990 @@
-use crate::project::Project;
-use crate::utils::{id_string, localization_getter, stringe};
+use crate::arb::{self, ArbDocument, Metadata};
+use crate::dart::{self, StringPart, Token, TokenKind};
+use crate::diagnostic::{self, Diagnostic, Format, Severity};
+use crate::icu;
+use crate::project::{Collision, KeyStrategy, Marker, PlaceholderFormat, Project};
+use crate::utils::{
+    dart_identifier, id_string, localization_call, lower_camel, placeholder_name, short_hash, slug,
+    stringe, transliterate, unique_name,
+};
 use crate::watcher::DirWatcher;
 use regex::Regex;
-use serde_json::{Map, Value};
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use tokio::time::sleep;
 
-type ExtractResult = Result<Option<(String, BTreeMap<String, String>)>, String>;
+type ExtractResult = Result<Option<(String, BTreeMap<String, Message>)>, String>;
 
-/// Extract marked strings from the file and replace them with `AppLocalizations` calls
-/// return the modifed content and Ordered mapping of the extracted strings.
-fn extract_from_file(file: &Path) -> ExtractResult {
-    let content = stringe(
-        "could not read the file content",
-        std::fs::read_to_string(file),
-    )?;
-    let translation_string_re = Regex::new("_(\"((?:\\\\\"|[^\"])*)\")").unwrap();
+/// Files about to be rewritten with their new content, nothing touches the disk
+/// before [write_changes] so the whole run can be shown as a diff instead.
+#[derive(Default)]
+struct Pending {
+    files: BTreeMap<PathBuf, String>,
+    /// What each file held when first read, nothing is written if that changed since.
+    read: BTreeMap<PathBuf, String>,
+    /// What came up while planning, reported once the changes are written, or not.
+    diagnostics: Vec<Diagnostic>,
+}
 
-    let mut new_strings = BTreeMap::new();
-    let mut new_content = content.clone();
-    let mut changed = false;
+impl Pending {
+    /// Reads `path` as it will be once the pending changes are written.
+    fn read(&mut self, path: &Path) -> Result<String, String> {
+        if let Some(content) = self.files.get(path) {
+            return Ok(content.clone());
+        }
+        if let Some(content) = self.read.get(path) {
+            return Ok(content.clone());
+        }
+        let content = stringe(
+            format!("could not read {}", path.display()).as_str(),
+            std::fs::read_to_string(path),
+        )?;
+        self.read.insert(path.to_path_buf(), content.clone());
+        Ok(content)
+    }
+    fn insert(&mut self, path: PathBuf, content: String) {
+        self.files.insert(path, content);
+    }
+    /// Leaves out the files that end up as they were.
+    fn changes(&self) -> Vec<(&Path, Option<&str>, &str)> {
+        self.files
+            .iter()
+            .map(|(path, content)| {
+                (
+                    path.as_path(),
+                    self.read.get(path).map(String::as_str),
+                    content.as_str(),
+                )
+            })
+            .filter(|(_, before, content)| *before != Some(*content))
+            .collect()
+    }
+}
 
-    for cap in translation_string_re
-        .captures_iter(&content)
-        .collect::<Vec<_>>()
-        .into_iter()
-        .rev()
-    {
-        let full_match = cap.get(0).unwrap();
-        let raw_inner_string = cap.get(1).unwrap().as_str();
-        let string_content = match serde_json::from_str(raw_inner_string) {
-            Ok(o) => o,
-            Err(e) => {
-                println!("Could not parse dart string({raw_inner_string}): {e}");
-                continue;
+/// An extracted message, `text` is the ICU message with `{placeholder}`s
+/// and `placeholders` are in the order of the getter arguments.
+/// `description` is the context for translators, from a comment or a second marker argument.
+#[derive(Debug, Clone)]
+struct Message {
+    text: String,
+    placeholders: Vec<Placeholder>,
+    description: Option<String>,
+    /// Literal `{`, `}` or plural `#` in the text, which only `use-escaping` can quote.
+    unquotable: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Placeholder {
+    name: String,
+    /// The dart type, `Object` unless something better could be inferred, gen-l10n then
+    /// takes whatever the expression is and calls `toString()` on it.
+    kind: String,
+}
+
+/// Placeholder types `flutter gen-l10n` formats in a locale-aware way.
+const FORMATTED_TYPES: [&str; 4] = ["int", "double", "num", "DateTime"];
+
+/// Comments starting with this describe the string next to them: `// l10n: checkout button`.
+const DESCRIPTION_TAG: &str = "l10n:";
+
+/// A marked string found in a dart file, `span` is what gets replaced by the getter call
+/// and `strip` a trailing marker comment removed along with it.
+/// `args` are the interpolated dart expressions, one for each placeholder,
+/// and `key_text` what the key is made from, the `other` case of plurals.
+struct MarkedString {
+    span: Range<usize>,
+    strip: Option<Range<usize>>,
+    message: Message,
+    args: Vec<String>,
+    key_text: String,
+}
+
+/// Reads the adjacent string literals starting at `code[j]` into a single message, returns
+/// it with the placeholder arguments, its text unquoted and the index of the following token.
+fn collect_strings(
+    code: &[&Token],
+    j: usize,
+    escaping: bool,
+) -> Option<(Message, Vec<String>, String, usize)> {
+    let mut message = Message {
+        text: String::new(),
+        placeholders: Vec::new(),
+        description: None,
+        unquotable: false,
+    };
+    let mut args: Vec<String> = Vec::new();
+    let mut plain = String::new();
+    let j = collect_strings_into(
+        code,
+        j,
+        escaping,
+        false,
+        &mut message,
+        &mut args,
+        &mut plain,
+    )?;
+    Some((message, args, plain, j))
+}
+
+/// Like [collect_strings] but appends to `message`, and the unquoted text to `plain`,
+/// interpolations of an expression already in `args` reuse its placeholder.
+fn collect_strings_into(
+    code: &[&Token],
+    mut j: usize,
+    escaping: bool,
+    in_plural: bool,
+    message: &mut Message,
+    args: &mut Vec<String>,
+    plain: &mut String,
+) -> Option<usize> {
+    let start = j;
+    while let Some(TokenKind::Str(s)) = code.get(j).map(|t| &t.kind) {
+        for part in &s.parts {
+            match part {
+                StringPart::Text(t) => {
+                    match icu::quote(t, escaping, in_plural) {
+                        Some(quoted) => message.text.push_str(&quoted),
+                        None => {
+                            message.unquotable = true;
+                            message.text.push_str(t);
+                        }
+                    }
+                    plain.push_str(t);
+                }
+                StringPart::Interpolation { expr, .. } => {
+                    let index = match args.iter().position(|a| a == expr) {
+                        Some(index) => index,
+                        None => {
+                            let taken: Vec<String> = message
+                                .placeholders
+                                .iter()
+                                .map(|p| p.name.clone())
+                                .collect();
+                            let name = unique_name(&placeholder_name(expr, args.len()), &taken);
+                            message.placeholders.push(Placeholder {
+                                name,
+                                kind: String::from("Object"),
+                            });
+                            args.push(expr.clone());
+                            args.len() - 1
+                        }
+                    };
+                    let placeholder = format!("{{{}}}", message.placeholders[index].name);
+                    message.text.push_str(&placeholder);
+                    plain.push_str(&placeholder);
+                }
             }
+        }
+        j += 1;
+    }
+    (j > start).then_some(j)
+}
+
+/// Matches the dotted `name` at `code[i]`, returns the index of the token following it.
+fn match_name(content: &str, code: &[&Token], i: usize, name: &str) -> Option<usize> {
+    let mut j = i;
+    for (n, segment) in name.split('.').enumerate() {
+        if n > 0 {
+            code.get(j).filter(|t| t.is_punct(content, "."))?;
+            j += 1;
+        }
+        code.get(j).filter(|t| t.is_ident(content, segment))?;
+        j += 1;
+    }
+    Some(j)
+}
+
+/// Looks for `name(` followed by one or more adjacent string literals and a closing `)`,
+/// with `glued` the name must touch the parenthesis, as `_(` does.
+fn find_marked_calls(
+    content: &str,
+    tokens: &[Token],
+    name: &str,
+    glued: bool,
+    escaping: bool,
+) -> Vec<MarkedString> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut marked = Vec::new();
+
+    let mut i = 0;
+    while i < code.len() {
+        let after_name = match_name(content, &code, i, name)
+            .filter(|_| i == 0 || !code[i - 1].is_punct(content, "."))
+            .filter(|&j| code.get(j).is_some_and(|t| t.is_punct(content, "(")))
+            .filter(|&j| !glued || code[j - 1].span.end == code[j].span.start);
+        let Some((mut message, args, plain, mut j)) =
+            after_name.and_then(|j| collect_strings(&code, j + 1, escaping))
+        else {
+            i += 1;
+            continue;
         };
+        if code.get(j).is_some_and(|t| t.is_punct(content, ",")) {
+            j += 1;
+            // `_("Back", "button on the checkout screen")`
+            if let Some((_, _, description, k)) = collect_strings(&code, j, escaping) {
+                message.description = Some(description);
+                j = k;
+                if code.get(j).is_some_and(|t| t.is_punct(content, ",")) {
+                    j += 1;
+                }
+            }
+        }
+        if code.get(j).is_some_and(|t| t.is_punct(content, ")")) {
+            let span = code[i].span.start..code[j].span.end;
+            if message.description.is_none() {
+                message.description = description_comment(content, tokens, &span);
+            }
+            marked.push(MarkedString {
+                span,
+                strip: None,
+                key_text: plain,
+                message,
+                args,
+            });
+            i = j + 1;
+        } else {
+            i += 1;
+        }
+    }
+    marked
+}
 
-        let id = id_string(string_content);
-        new_content.replace_range(
-            full_match.start()..full_match.end(),
-            &localization_getter(&id),
-        );
-        new_strings.entry(id).or_insert(string_content.to_string());
-        changed = true;
+/// Tells if `comment` is a `// {tag}` marker, or `// {tag}: description`, and returns the
+/// description if there is one.
+pub fn marker_comment(comment: &str, tag: &str) -> Option<Option<String>> {
+    let rest = comment.strip_prefix("//")?.trim().strip_prefix(tag)?;
+    match rest.strip_prefix(':') {
+        Some(d) => Some(Some(d.trim().to_string()).filter(|d| !d.is_empty())),
+        None if rest.is_empty() => Some(None),
+        None => None,
     }
+}
 
-    if changed {
-        Ok(Some((new_content, new_strings)))
-    } else {
-        Ok(None)
+/// Looks for `// {tag}` line comments and marks the strings on the same line before them.
+fn find_marked_lines(
+    content: &str,
+    tokens: &[Token],
+    tag: &str,
+    escaping: bool,
+) -> Vec<MarkedString> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut marked = Vec::new();
+
+    for comment in tokens.iter().filter(|t| t.is_comment()) {
+        let Some(description) = marker_comment(comment.text(content), tag) else {
+            continue;
+        };
+        let line_start = content[..comment.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let first = marked.len();
+        let mut i = code.partition_point(|t| t.span.start < line_start);
+        while i < code.len() && code[i].span.start < comment.span.start {
+            match collect_strings(&code, i, escaping) {
+                Some((mut message, args, plain, j)) => {
+                    message.description = description.clone();
+                    marked.push(MarkedString {
+                        span: code[i].span.start..code[j - 1].span.end,
+                        strip: None,
+                        key_text: plain,
+                        message,
+                        args,
+                    });
+                    i = j;
+                }
+                None => i += 1,
+            }
+        }
+        if marked.len() > first {
+            // take the spaces before the comment along with it
+            let strip_start = content[..comment.span.start]
+                .trim_end_matches([' ', '\t'])
+                .len();
+            marked.last_mut().unwrap().strip = Some(strip_start..comment.span.end);
+        }
     }
+    marked
 }
 
-fn update_arb_file(
-    project: &Project,
-    new_strings: &BTreeMap<String, String>,
-) -> Result<bool, String> {
-    if new_strings.is_empty() {
-        return Ok(false);
+/// Finds a `// l10n: description` comment after `span` on the same line,
+/// or alone on the line just above it.
+fn description_comment(content: &str, tokens: &[Token], span: &Range<usize>) -> Option<String> {
+    dart::attached_comments(content, tokens, span)
+        .into_iter()
+        .find_map(|t| {
+            let description = t.text(content).strip_prefix("//")?.trim();
+            Some(
+                description
+                    .strip_prefix(DESCRIPTION_TAG)?
+                    .trim()
+                    .to_string(),
+            )
+        })
+        .filter(|d| !d.is_empty())
+}
+
+/// Looks for `_p(count, one: "1 item", other: "$count items")` plural markers, or
+/// `_s(gender, male: "he", other: "they")` select ones, `kind` being `plural` or `select`.
+/// An optional `description:` argument works like the second argument of plain markers.
+fn find_marked_cases(
+    content: &str,
+    tokens: &[Token],
+    name: &str,
+    kind: &str,
+    escaping: bool,
+) -> Vec<MarkedString> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let mut marked = Vec::new();
+
+    let mut i = 0;
+    while i < code.len() {
+        let case = match_name(content, &code, i, name)
+            .filter(|_| i == 0 || !code[i - 1].is_punct(content, "."))
+            .filter(|&j| code.get(j).is_some_and(|t| t.is_punct(content, "(")))
+            .and_then(|j| read_cases(content, &code, j + 1, kind, escaping));
+        match case {
+            Some((mut message, args, key_text, j)) => {
+                let span = code[i].span.start..code[j].span.end;
+                if message.description.is_none() {
+                    message.description = description_comment(content, tokens, &span);
+                }
+                marked.push(MarkedString {
+                    span,
+                    strip: None,
+                    message,
+                    args,
+                    key_text,
+                });
+                i = j + 1;
+            }
+            None => i += 1,
+        }
     }
+    marked
+}
 
-    let arb_path = project.arb_template_path();
-    let arb_content = stringe(
-        "Could not read arb file content",
-        std::fs::read_to_string(&arb_path),
-    )?;
-    let mut arb_data: BTreeMap<String, Value> =
-        serde_json::from_str(&arb_content).map_err(|e| e.to_string())?;
+/// Reads the arguments of a plural or select marker starting at `code[j]`, right after
+/// the parenthesis, returns the message, its arguments, the `other` case and the index
+/// of the closing parenthesis.
+fn read_cases(
+    content: &str,
+    code: &[&Token],
+    mut j: usize,
+    kind: &str,
+    escaping: bool,
+) -> Option<(Message, Vec<String>, String, usize)> {
+    // the first argument is any dart expression, up to the first top-level comma
+    let start = j;
+    let mut depth = 0;
+    while let Some(t) = code.get(j) {
+        match t.text(content) {
+            "(" | "[" | "{" if t.kind == TokenKind::Punct => depth += 1,
+            ")" | "]" | "}" if t.kind == TokenKind::Punct && depth == 0 => return None,
+            ")" | "]" | "}" if t.kind == TokenKind::Punct => depth -= 1,
+            "," if t.kind == TokenKind::Punct && depth == 0 => break,
+            _ => {}
+        }
+        j += 1;
+    }
+    if j == start || j >= code.len() {
+        return None;
+    }
+    let expr = content[code[start].span.start..code[j - 1].span.end].to_string();
+    let name = placeholder_name(&expr, 0);
+    let mut message = Message {
+        text: format!("{{{name}, {kind}, "),
+        placeholders: vec![Placeholder {
+            name,
+            kind: String::from(if kind == "plural" { "int" } else { "String" }),
+        }],
+        description: None,
+        unquotable: false,
+    };
+    let mut args = vec![expr];
+    let mut other = None;
+    j += 1;
 
-    let mut changed = false;
-    for (key, value) in new_strings {
-        if !arb_data.contains_key(key) {
-            arb_data.insert(key.clone(), Value::String(value.clone()));
-            let metadata_key = format!("@{}", key);
-            let metadata = create_metadata(value);
-            println!("[extractor] Adding new key: {key} with metadata {metadata:?}");
-            arb_data
-                .entry(metadata_key)
-                .or_insert(Value::Object(metadata));
-            changed = true;
+    while !code.get(j)?.is_punct(content, ")") {
+        let case = code
+            .get(j)
+            .filter(|t| t.kind == TokenKind::Ident)?
+            .text(content);
+        code.get(j + 1).filter(|t| t.is_punct(content, ":"))?;
+        if case == "description" {
+            let (_, _, description, k) = collect_strings(code, j + 2, escaping)?;
+            message.description = Some(description);
+            j = k;
+        } else {
+            // the CLDR categories, `one` is also 21 or 31 in some languages, not `=1`
+            if kind == "plural" && !["zero", "one", "two", "few", "many", "other"].contains(&case) {
+                return None;
+            }
+            message.text.push_str(case);
+            message.text.push('{');
+            let mut plain = String::new();
+            let in_plural = kind == "plural";
+            j = collect_strings_into(
+                code,
+                j + 2,
+                escaping,
+                in_plural,
+                &mut message,
+                &mut args,
+                &mut plain,
+            )?;
+            if case == "other" {
+                other = Some(plain);
+            }
+            message.text.push_str("} ");
+        }
+        if code.get(j).is_some_and(|t| t.is_punct(content, ",")) {
+            j += 1;
         }
     }
+    // icu requires the `other` case
+    let other = other?;
+    message.text.pop();
+    message.text.push('}');
+    Some((message, args, other, j))
+}
 
-    if changed {
-        let updated_content = serde_json::to_string_pretty(&arb_data).map_err(|e| e.to_string())?;
-        stringe(
-            "could not write back arb file content after adding new keys",
-            std::fs::write(&arb_path, updated_content),
-        )?;
+fn find_marked_strings(p: &Project, content: &str, tokens: &[Token]) -> Vec<MarkedString> {
+    let escaping = p.use_escaping;
+    let mut marked = match &p.config.marker {
+        Marker::Prefix(prefix) => find_marked_calls(content, tokens, prefix, true, escaping),
+        Marker::Function(name) => find_marked_calls(content, tokens, name, false, escaping),
+        Marker::Comment(tag) => find_marked_lines(content, tokens, tag, escaping),
+    };
+    marked.extend(find_marked_cases(
+        content,
+        tokens,
+        &p.config.plural_marker,
+        "plural",
+        escaping,
+    ));
+    marked.extend(find_marked_cases(
+        content,
+        tokens,
+        &p.config.select_marker,
+        "select",
+        escaping,
+    ));
+    marked.sort_by_key(|m| m.span.start);
+    marked
+}
+
+/// Infers the type of an interpolated expression, from a `${count as int}` cast or
+/// `${count /* int */}` annotation, from literals, or from a `int count` declaration in
+/// scope at `offset`. Returns the type with the expression cleaned of the annotation comment.
+fn infer_type(
+    content: &str,
+    tokens: &[Token],
+    offset: usize,
+    expr: &str,
+) -> (Option<String>, String) {
+    let Ok(expr_tokens) = dart::tokenize(expr) else {
+        return (None, expr.to_string());
+    };
+    let annotation = expr_tokens.iter().find_map(|t| {
+        let text = t.text(expr);
+        let kind = text.strip_prefix("/*")?.strip_suffix("*/")?.trim();
+        FORMATTED_TYPES
+            .contains(&kind)
+            .then_some((kind, t.span.clone()))
+    });
+    if let Some((kind, span)) = annotation {
+        let cleaned = format!("{}{}", &expr[..span.start], &expr[span.end..]);
+        return (Some(kind.to_string()), cleaned.trim().to_string());
     }
 
-    Ok(changed)
+    let code: Vec<&Token> = expr_tokens.iter().filter(|t| !t.is_comment()).collect();
+    let known = |t: &Token| {
+        let text = t.text(expr);
+        (t.kind == TokenKind::Ident && FORMATTED_TYPES.contains(&text)).then(|| text.to_string())
+    };
+    let kind = match code.as_slice() {
+        // `count as int`, `count as int?` is not something gen-l10n accepts
+        [.., as_, kind] if as_.is_ident(expr, "as") => known(kind),
+        [number] if number.kind == TokenKind::Number => {
+            let text = number.text(expr);
+            let float = !text.starts_with("0x") && text.contains(['.', 'e', 'E']);
+            Some(String::from(if float { "double" } else { "int" }))
+        }
+        [.., dot, length] if dot.is_punct(expr, ".") && length.is_ident(expr, "length") => {
+            Some(String::from("int"))
+        }
+        [date_time, dot, ..] if date_time.is_ident(expr, "DateTime") && dot.is_punct(expr, ".") => {
+            Some(String::from("DateTime"))
+        }
+        // `count`, `widget.count` or `this.count`, looked up in the declarations in scope
+        [.., dot, name] if name.kind == TokenKind::Ident && dot.is_punct(expr, ".") => {
+            dart::declared_type(content, tokens, offset, name.text(expr), true)
+        }
+        [.., name] if name.kind == TokenKind::Ident => {
+            dart::declared_type(content, tokens, offset, name.text(expr), false)
+        }
+        _ => None,
+    };
+    (
+        kind.filter(|k| FORMATTED_TYPES.contains(&k.as_str())),
+        expr.to_string(),
+    )
 }
 
-fn create_metadata(txt: &str) -> Map<String, Value> {
-    let mut metadata = Map::new();
+/// The message `id` holds, either in the strings being extracted or in the template arb file.
+fn key_holds<'a>(
+    new_strings: &'a BTreeMap<String, Message>,
+    existing: &'a ArbDocument,
+    id: &str,
+) -> Option<&'a str> {
+    new_strings
+        .get(id)
+        .map(|m| m.text.as_str())
+        .or_else(|| existing.messages.get(id).map(String::as_str))
+}
 
-    let regex = Regex::new("\\{(\\w+)\\}").unwrap();
+/// Makes up the key of a marked string with the configured naming strategy.
+fn make_key(p: &Project, file: &Path, content: &str, tokens: &[Token], m: &MarkedString) -> String {
+    let naming = &p.config.keys;
+    // placeholders are named after what they hold, so `Hello {name}` makes `helloName`
+    let mut text = m.key_text.replace(['{', '}'], "");
+    if naming.transliterate {
+        text = transliterate(&text);
+    }
+    let file_prefix = || {
+        lower_camel(
+            file.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default(),
+        )
+    };
+    let (prefix, text) = match naming.strategy {
+        KeyStrategy::Sentence => (None, id_string(&text)),
+        KeyStrategy::Slug => (None, slug(&text, naming.max_length)),
+        KeyStrategy::FilePrefixed => (Some(file_prefix()), slug(&text, naming.max_length)),
+        KeyStrategy::ClassPrefixed => (
+            Some(
+                dart::enclosing_class(content, tokens, m.span.start)
+                    .map_or_else(file_prefix, |c| lower_camel(&c)),
+            ),
+            slug(&text, naming.max_length),
+        ),
+        KeyStrategy::Hash => return short_hash(&m.message.text),
+    };
+    // nothing left of the text, letters without an ASCII spelling or the like
+    if text.is_empty() {
+        return short_hash(&m.message.text);
+    }
+    let key = match prefix {
+        Some(prefix) => format!("{prefix}_{text}"),
+        None => text,
+    };
+    dart_identifier(&key).unwrap_or_else(|| short_hash(&m.message.text))
+}
 
-    let mut placeholders = Map::new();
-    for captures in regex.captures_iter(txt) {
-        let mut val = Map::new();
-        val.insert(String::from("type"), Value::String(String::from("String")));
-        placeholders.insert(
-            String::from(captures.get(1).unwrap().as_str()),
-            Value::Object(val),
-        );
+/// Extract marked strings from the file and replace them with `AppLocalizations` calls
+/// return the modifed content and Ordered mapping of the extracted strings.
+fn extract_from_file(
+    p: &Project,
+    file: &Path,
+    content: &str,
+    existing: &ArbDocument,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> ExtractResult {
+    let tokens = dart::tokenize(content).map_err(|e| format!("could not parse dart file: {e}"))?;
+    let mut marked = find_marked_strings(p, content, &tokens);
+    if marked.is_empty() {
+        return Ok(None);
     }
-    if !placeholders.is_empty() {
-        metadata.insert(String::from("placeholders"), Value::Object(placeholders));
+    for m in &mut marked {
+        for (placeholder, arg) in m.message.placeholders.iter_mut().zip(m.args.iter_mut()) {
+            let (kind, cleaned) = infer_type(content, &tokens, m.span.start, arg);
+            if let Some(kind) = kind {
+                placeholder.kind = kind;
+            }
+            *arg = cleaned;
+        }
+    }
+
+    let mut new_strings = BTreeMap::new();
+    let mut new_content = content.to_string();
+    let template = p.call_site_template();
+    let needs_context = template.contains("context");
+
+    let diagnostic = |severity, code, at, message: String| {
+        Diagnostic::new(severity, code, &p.root_dir, file, message).at(at)
+    };
+
+    // keys are made in file order, so the first of two colliding strings keeps the plain key
+    let mut rewrites = Vec::new();
+    for m in marked {
+        let template = if needs_context && !dart::context_in_scope(content, &tokens, m.span.start) {
+            match &p.config.context_free_call_site {
+                Some(template) => template,
+                None => {
+                    diagnostics.push(diagnostic(
+                        Severity::Warning,
+                        "no-context",
+                        dart::line_col(content, m.span.start),
+                        format!(
+                            "no BuildContext `context` in scope, not rewriting {:?}",
+                            m.message.text
+                        ),
+                    ));
+                    continue;
+                }
+            }
+        } else {
+            &template
+        };
+        if m.message.unquotable {
+            diagnostics.push(diagnostic(
+                Severity::Warning,
+                "needs-escaping",
+                dart::line_col(content, m.span.start),
+                format!(
+                    "`{{`, `}}` or `#` in {:?} need `use-escaping: true` in l10n.yaml, not rewriting it",
+                    m.key_text
+                ),
+            ));
+            continue;
+        }
+        let key = make_key(p, file, content, &tokens, &m);
+        let id = match key_holds(&new_strings, existing, &key) {
+            Some(other) if other != m.message.text => {
+                let at = dart::line_col(content, m.span.start);
+                match p.config.keys.collision {
+                    Collision::Refuse => {
+                        diagnostics.push(diagnostic(
+                            Severity::Warning,
+                            "key-collision",
+                            at,
+                            format!(
+                                "key `{key}` already holds {other:?}, not rewriting {:?}",
+                                m.message.text
+                            ),
+                        ));
+                        continue;
+                    }
+                    Collision::Suffix => {
+                        let id = (2..)
+                            .map(|i| format!("{key}{i}"))
+                            .find(|id| {
+                                key_holds(&new_strings, existing, id)
+                                    .is_none_or(|t| t == m.message.text)
+                            })
+                            .unwrap();
+                        diagnostics.push(diagnostic(
+                            Severity::Note,
+                            "key-collision",
+                            at,
+                            format!("key `{key}` already holds {other:?}, using `{id}`"),
+                        ));
+                        id
+                    }
+                }
+            }
+            _ => key,
+        };
+        new_strings.entry(id.clone()).or_insert(m.message.clone());
+        rewrites.push((m, id, template));
+    }
+    if rewrites.is_empty() {
+        return Ok(None);
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    for (m, id, template) in rewrites {
+        // the getter call is not constant, `const Text(_("Save"))` would not compile anymore
+        for (span, replacement) in dart::invalidated_consts(content, &tokens, m.span.start) {
+            edits.push((span, replacement.to_string()));
+        }
+        if let Some(strip) = m.strip {
+            edits.push((strip, String::new()));
+        }
+        edits.push((
+            m.span,
+            localization_call(template, &p.output_class, &id, &m.args),
+        ));
+    }
+    edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+    edits.dedup_by_key(|(span, _)| span.start);
+    for (span, replacement) in edits {
+        new_content.replace_range(span, &replacement);
+    }
+
+    Ok(Some((new_content, new_strings)))
+}
+
+/// Adds the new strings and their metadata to the template, returns whether any was missing.
+fn update_arb_file(
+    project: &Project,
+    arb_data: &mut ArbDocument,
+    new_strings: &BTreeMap<String, Message>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> bool {
+    let mut changed = false;
+    for (key, value) in new_strings {
+        if !arb_data.messages.contains_key(key) {
+            arb_data.messages.insert(key.clone(), value.text.clone());
+            diagnostics.push(Diagnostic::new(
+                Severity::Note,
+                "new-key",
+                &project.root_dir,
+                &project.arb_template_path(),
+                format!("new key `{key}`: {:?}", value.text),
+            ));
+            let metadata = create_metadata(project, value);
+            arb_data.metadata.entry(key.clone()).or_insert(metadata);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn create_metadata(project: &Project, message: &Message) -> Metadata {
+    let mut metadata = Metadata {
+        description: message.description.clone(),
+        ..Default::default()
+    };
+    for placeholder in &message.placeholders {
+        let mut val = arb::Placeholder {
+            kind: Some(placeholder.kind.clone()),
+            ..Default::default()
+        };
+        match project.placeholder_format(&placeholder.kind) {
+            Some(PlaceholderFormat::Format(format)) => val.format = Some(format),
+            Some(PlaceholderFormat::Full {
+                format,
+                optional_parameters,
+            }) => {
+                val.format = Some(format);
+                val.optional_parameters = optional_parameters;
+            }
+            None => {}
+        }
+        metadata.placeholders.push((placeholder.name.clone(), val));
     }
     metadata
 }
 
-fn ensure_localization_import(project: &Project, content: &mut String) {
+/// Adds the localizations import after the file's directives, among the other imports in
+/// sorted order. `part of` files cannot have imports so the parent library gets it instead.
+fn ensure_localization_import(
+    project: &Project,
+    path: &Path,
+    content: &mut String,
+    pending: &mut Pending,
+) -> Result<(), String> {
     let l10n_path_str = project
         .l10n_dir
         .strip_prefix("lib/")
         .unwrap_or(&project.l10n_dir)
         .to_str()
         .unwrap();
-    let import_statement = format!(
-        "import 'package:{}/{}/{}';\n",
+    let uri = format!(
+        "package:{}/{}/{}",
         project.name, l10n_path_str, project.localizations_file
     );
+    let import_statement = format!("import '{uri}';\n");
     let import_re = Regex::new(&format!("import.*{}", project.localizations_file)).unwrap();
 
-    if !import_re.is_match(content) {
-        print!("[extractor] Adding {}", import_statement);
-        content.insert_str(0, &import_statement);
+    if import_re.is_match(content) {
+        return Ok(());
+    }
+    let tokens = dart::tokenize(content)?;
+    let directives = dart::directives(content, &tokens);
+
+    if let Some(part_of) = directives.iter().find(|d| d.keyword == "part of") {
+        let parent = parent_library(project, path, &part_of.target)
+            .ok_or_else(|| format!("could not find the library {:?} is part of", part_of.target))?;
+        let mut parent_content = pending.read(&parent)?;
+        let before = parent_content.clone();
+        ensure_localization_import(project, &parent, &mut parent_content, pending)?;
+        if parent_content != before {
+            pending.insert(parent, parent_content);
+        }
+        return Ok(());
+    }
+
+    let line_start = |offset: usize| content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = |offset: usize| {
+        content[offset..]
+            .find('\n')
+            .map_or(content.len(), |i| offset + i + 1)
+    };
+    let imports: Vec<_> = directives
+        .iter()
+        .filter(|d| d.keyword == "import")
+        .collect();
+    let (at, text) = if let Some(next) = imports.iter().find(|d| d.target > uri) {
+        (line_start(next.span.start), import_statement.clone())
+    } else if let Some(last) = imports.last() {
+        (line_end(last.span.end), import_statement.clone())
+    } else if let Some(first) = directives.iter().find(|d| d.keyword != "library") {
+        // exports and parts come after the imports
+        (
+            line_start(first.span.start),
+            format!("{import_statement}\n"),
+        )
+    } else if let Some(library) = directives.first() {
+        (line_end(library.span.end), format!("\n{import_statement}"))
+    } else {
+        // after license headers and `// ignore_for_file` comments
+        match tokens.iter().find(|t| !t.is_comment()) {
+            Some(first) => (
+                line_start(first.span.start),
+                format!("{import_statement}\n"),
+            ),
+            None => (content.len(), import_statement.clone()),
+        }
+    };
+    pending.diagnostics.push(Diagnostic::new(
+        Severity::Note,
+        "import-added",
+        &project.root_dir,
+        path,
+        format!("adding {}", import_statement.trim_end()),
+    ));
+    if at == content.len() && !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    let at = at.min(content.len());
+    content.insert_str(at, &text);
+    Ok(())
+}
+
+/// Finds the library a `part of` file belongs to, `target` being its uri or library name.
+fn parent_library(project: &Project, path: &Path, target: &str) -> Option<PathBuf> {
+    if let Some(package_path) = target.strip_prefix(&format!("package:{}/", project.name)) {
+        return Some(project.root_dir.join("lib").join(package_path));
+    }
+    if target.ends_with(".dart") {
+        return Some(path.parent()?.join(target));
+    }
+    project.dart_files().into_iter().find(|file| {
+        std::fs::read_to_string(file).is_ok_and(|content| {
+            dart::tokenize(&content).is_ok_and(|tokens| {
+                dart::directives(&content, &tokens)
+                    .iter()
+                    .any(|d| d.keyword == "library" && d.target == target)
+            })
+        })
+    })
+}
+
+/// Extracts the marked strings of `path`, adding the file, the template arb file and
+/// possibly a parent library to `pending`.
+fn plan_file(p: &Project, path: &Path, pending: &mut Pending) -> Result<(), String> {
+    let template_path = p.arb_template_path();
+    let mut arb_data = ArbDocument::parse(&pending.read(&template_path)?)
+        .map_err(|e| format!("could not decode the template arb file: {e}"))?;
+    let content = pending.read(path)?;
+
+    if let Some((mut modified_content, new_strings)) =
+        extract_from_file(p, path, &content, &arb_data, &mut pending.diagnostics)?
+    {
+        if update_arb_file(p, &mut arb_data, &new_strings, &mut pending.diagnostics) {
+            pending.insert(template_path, arb_data.to_json(p.config.new_key_position)?);
+        }
+        // even when all keys already existed, this file may not import them yet
+        ensure_localization_import(p, path, &mut modified_content, pending)?;
+        pending.insert(path.to_path_buf(), modified_content);
     }
+    Ok(())
+}
+
+/// Writes the pending changes, all of them or, when one of the files changed on disk
+/// since it was read, none. Returns whether they were written.
+fn write_changes(p: &Project, pending: &Pending) -> Result<bool, String> {
+    p.journal.replace_all(&pending.changes())
+}
+
+fn print_updated(pending: &Pending) {
+    for (path, _, _) in pending.changes() {
+        println!("[extractor] Updated {}.", path.display());
+    }
+}
+
+/// A file [plan_file] gave up on.
+fn failed(p: &Project, path: &Path, error: String) -> Diagnostic {
+    Diagnostic::new(
+        Severity::Error,
+        "extract-failed",
+        &p.root_dir,
+        path,
+        format!("could not extract the strings: {error}"),
+    )
 }
 
 fn process_file(p: &Project, path: &Path) -> Result<(), String> {
-    match extract_from_file(path) {
-        Ok(Some((mut modified_content, new_strings))) => {
-            if update_arb_file(p, &new_strings)? {
-                ensure_localization_import(p, &mut modified_content);
+    // planned again from what is on disk when something else wrote to the files meanwhile
+    for _ in 0..3 {
+        let mut pending = Pending::default();
+        match plan_file(p, path, &mut pending) {
+            Ok(()) => {
+                if write_changes(p, &pending)? {
+                    diagnostic::report(Format::Text, "extractor", &pending.diagnostics);
+                    print_updated(&pending);
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                diagnostic::report(Format::Text, "extractor", &[failed(p, path, e)]);
+                return Ok(());
             }
-            std::fs::write(path, modified_content).map_err(|e| e.to_string())?;
-            println!("[extractor] Updated {}.", path.display());
         }
-        Ok(None) => {}
-        Err(e) => {
-            println!(
-                "[extractor] Error processing file {}: {}",
-                path.display(),
-                e
+    }
+    println!(
+        "[extractor] {} or the arb files keep changing on disk, skipping it",
+        path.display()
+    );
+    Ok(())
+}
+
+/// `arb-util extract`, a single extraction over all of `lib/`. With `dry_run` nothing is
+/// written and a unified diff of the changes is printed instead.
+/// Returns whether there were changes, or marked strings left as they were.
+pub fn extract_once(p: &Project, dry_run: bool, format: Format) -> Result<bool, String> {
+    let mut pending = Pending::default();
+    for path in p.dart_files() {
+        if let Err(e) = plan_file(p, &path, &mut pending) {
+            pending.diagnostics.push(failed(p, &path, e));
+        }
+    }
+    let changes = pending.changes();
+    // strings left marked, without a context or with a taken key, or in files not understood
+    let left = pending
+        .diagnostics
+        .iter()
+        .any(|d| d.severity != Severity::Note);
+    if !dry_run && !write_changes(p, &pending)? {
+        return Err(String::from(
+            "files changed on disk during the extraction, nothing was written, run it again",
+        ));
+    }
+    diagnostic::report(format, "extractor", &pending.diagnostics);
+    if !format.is_text() {
+        return Ok(!changes.is_empty() || left);
+    }
+    if dry_run {
+        for (path, before, content) in &changes {
+            let name = path.strip_prefix(&p.root_dir).unwrap_or(path).display();
+            print!(
+                "{}",
+                similar::TextDiff::from_lines(before.unwrap_or_default(), *content)
+                    .unified_diff()
+                    .header(&format!("a/{name}"), &format!("b/{name}"))
             );
         }
+    } else {
+        print_updated(&pending);
     }
-    Ok(())
+    Ok(!changes.is_empty() || left)
 }
 
-pub async fn run(p: Project) -> Result<(), String> {
+pub async fn run(mut p: Project) -> Result<(), String> {
     let lib_dir = p.root_dir.join("lib");
 
     println!("[extractor] Extractor started, making initial run");
@@ -163,8 +992,10 @@ pub async fn run(p: Project) -> Result<(), String> {
     let mut watcher = DirWatcher::new(&lib_dir, true)?;
     while let Some(path) = watcher.next().await {
         sleep(std::time::Duration::from_millis(300)).await; // Debounce
+        p.journal.new_run();
         if path.is_file()
-            && path.extension().is_some_and(|ext| ext == "dart")
+            && p.is_source_file(&path)
+            && !p.is_ignored(&path)
             && let Err(e) = process_file(&p, &path)
         {
             println!(
@@ -173,18 +1004,49 @@ pub async fn run(p: Project) -> Result<(), String> {
                 e
             );
         } else {
-            for entry in stringe(
-                "Could not list files in lib/ directory",
-                std::fs::read_dir(&lib_dir),
-            )?
-            .flatten()
-            {
-                let path = entry.path();
-                if path.is_file() && path.extension().is_some_and(|ext| ext == "dart") {
-                    process_file(&p, path.as_path())?;
-                }
+            for path in p.dart_files() {
+                process_file(&p, path.as_path())?;
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(src: &str, escaping: bool) -> (Message, Vec<String>, String) {
+        let tokens = dart::tokenize(src).unwrap();
+        let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+        let (message, args, plain, _) = collect_strings(&code, 0, escaping).unwrap();
+        (message, args, plain)
+    }
+
+    #[test]
+    fn literal_text_is_quoted() {
+        let src = r"'Use {braces} and it\'s $n'";
+        let (message, args, plain) = collect(src, true);
+        assert_eq!(message.text, "Use '{'braces'}' and it''s {n}");
+        assert!(!message.unquotable);
+        assert_eq!(args, ["n"]);
+        assert_eq!(plain, "Use {braces} and it's {n}");
+        assert_eq!(
+            icu::arguments(&icu::parse(&message.text, true).unwrap()),
+            ["n"]
+        );
+
+        // without `use-escaping` braces can't be told from placeholders
+        assert!(collect(src, false).0.unquotable);
+        let (message, _, _) = collect(r#""it's $n""#, false);
+        assert_eq!(message.text, "it's {n}");
+        assert!(!message.unquotable);
+    }
+
+    #[test]
+    fn untyped_placeholders_are_objects() {
+        let (message, args, _) = collect(r#""Total: ${cart.total}""#, false);
+        assert_eq!(message.placeholders[0].kind, "Object");
+        assert_eq!(args, ["cart.total"]);
+    }
+}
@@ -0,0 +1,508 @@
+use std::fmt;
+
+/// An ICU MessageFormat message, as `flutter gen-l10n` understands them: text, `{arguments}`,
+/// and `plural`, `selectordinal` and `select` choices between nested messages.
+pub type Message = Vec<Node>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    /// `{name}`, or `{name, number, compact}` with a format and a style.
+    Argument {
+        name: String,
+        format: Option<String>,
+        style: Option<String>,
+    },
+    /// `{count, plural, =0{none} one{# item} other{# items}}`, `selectordinal` when `ordinal`.
+    Plural {
+        name: String,
+        ordinal: bool,
+        offset: Option<i64>,
+        cases: Vec<Case>,
+    },
+    /// `{gender, select, male{he} female{she} other{they}}`
+    Select {
+        name: String,
+        cases: Vec<Case>,
+    },
+    /// `#` inside a plural case, the number itself.
+    Pound,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// `one`, `=0`, `male`...
+    pub selector: String,
+    pub message: Message,
+}
+
+/// Where a message stops making sense, `offset` is in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+/// Parses `src`, with `escaping` a `'` quotes the braces after it and `''` is a single one,
+/// as with `use-escaping: true` in `l10n.yaml`. Otherwise apostrophes are just text.
+pub fn parse(src: &str, escaping: bool) -> Result<Message, ParseError> {
+    let mut parser = Parser {
+        src,
+        pos: 0,
+        escaping,
+    };
+    parser.message(false, false)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    escaping: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            offset: self.pos,
+            message: message.into(),
+        })
+    }
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+    /// A name, selector or format, up to a space or a brace.
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !"{},".contains(c))
+        {
+            self.bump();
+        }
+        &self.src[start..self.pos]
+    }
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_spaces();
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.error(format!("expected `{c}`"))
+        }
+    }
+
+    /// Reads nodes up to the end of `src`, or the `}` closing a case when `nested`.
+    fn message(&mut self, in_plural: bool, nested: bool) -> Result<Message, ParseError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '}' if nested => break,
+                '}' => return self.error("unmatched `}`"),
+                '{' => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    self.bump();
+                    nodes.push(self.argument(in_plural)?);
+                }
+                '#' if in_plural => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    self.bump();
+                    nodes.push(Node::Pound);
+                }
+                '\'' if self.escaping => {
+                    self.bump();
+                    match self.peek() {
+                        Some('\'') => {
+                            self.bump();
+                            text.push('\'');
+                        }
+                        Some(c) if "{}|".contains(c) || (c == '#' && in_plural) => {
+                            // quoted up to the next lone apostrophe, or the end
+                            while let Some(c) = self.bump() {
+                                if c == '\'' {
+                                    if self.peek() == Some('\'') {
+                                        self.bump();
+                                    } else {
+                                        break;
+                                    }
+                                }
+                                text.push(c);
+                            }
+                        }
+                        _ => text.push('\''),
+                    }
+                }
+                c => {
+                    self.bump();
+                    text.push(c);
+                }
+            }
+        }
+        if nested && self.peek().is_none() {
+            return self.error("unclosed `{`");
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(nodes)
+    }
+
+    /// Reads what follows a `{`, up to and including its `}`.
+    fn argument(&mut self, in_plural: bool) -> Result<Node, ParseError> {
+        self.skip_spaces();
+        let name = self.word().to_string();
+        if name.is_empty() {
+            return self.error("expected an argument name");
+        }
+        self.skip_spaces();
+        match self.bump() {
+            Some('}') => {
+                return Ok(Node::Argument {
+                    name,
+                    format: None,
+                    style: None,
+                });
+            }
+            Some(',') => {}
+            _ => return self.error(format!("expected `,` or `}}` after `{name}`")),
+        }
+        self.skip_spaces();
+        let format = self.word().to_string();
+        match format.as_str() {
+            "plural" | "selectordinal" => {
+                self.expect(',')?;
+                self.skip_spaces();
+                let offset = match self.rest().strip_prefix("offset:") {
+                    Some(_) => {
+                        self.pos += "offset:".len();
+                        self.skip_spaces();
+                        let n = self.word();
+                        match n.parse() {
+                            Ok(n) => Some(n),
+                            Err(_) => return self.error(format!("invalid offset `{n}`")),
+                        }
+                    }
+                    None => None,
+                };
+                let cases = self.cases(true)?;
+                Ok(Node::Plural {
+                    name,
+                    ordinal: format == "selectordinal",
+                    offset,
+                    cases,
+                })
+            }
+            "select" => {
+                self.expect(',')?;
+                let cases = self.cases(in_plural)?;
+                Ok(Node::Select { name, cases })
+            }
+            "" => self.error(format!("expected a format after `{name},`")),
+            _ => {
+                self.skip_spaces();
+                let style = match self.bump() {
+                    Some('}') => {
+                        return Ok(Node::Argument {
+                            name,
+                            format: Some(format),
+                            style: None,
+                        });
+                    }
+                    Some(',') => {
+                        let start = self.pos;
+                        while self.peek().is_some_and(|c| c != '}') {
+                            self.bump();
+                        }
+                        self.src[start..self.pos].trim().to_string()
+                    }
+                    _ => return self.error(format!("expected `,` or `}}` after `{format}`")),
+                };
+                self.expect('}')?;
+                Ok(Node::Argument {
+                    name,
+                    format: Some(format),
+                    style: Some(style),
+                })
+            }
+        }
+    }
+
+    /// Reads `selector{message}` cases up to and including the closing `}`.
+    fn cases(&mut self, in_plural: bool) -> Result<Vec<Case>, ParseError> {
+        let mut cases = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('}') => {
+                    self.bump();
+                    break;
+                }
+                None => return self.error("unclosed `{`"),
+                _ => {}
+            }
+            let selector = self.word().to_string();
+            if selector.is_empty() {
+                return self.error("expected a case selector");
+            }
+            if cases.iter().any(|c: &Case| c.selector == selector) {
+                return self.error(format!("duplicate case `{selector}`"));
+            }
+            self.expect('{')?;
+            let message = self.message(in_plural, true)?;
+            self.expect('}')?;
+            cases.push(Case { selector, message });
+        }
+        if !cases.iter().any(|c| c.selector == "other") {
+            return self.error("missing the `other` case");
+        }
+        Ok(cases)
+    }
+}
+
+/// Quotes the literal `text` to go in a message: `{`, `}`, `#` when `in_plural`, and with
+/// `escaping` apostrophes. Without `escaping` braces and `#` can't be written, `None` then.
+pub fn quote(text: &str, escaping: bool, in_plural: bool) -> Option<String> {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '{' | '}' | '#' if !escaping && (c != '#' || in_plural) => return None,
+            '{' | '}' => out.push_str(&format!("'{c}'")),
+            '#' if in_plural => out.push_str("'#'"),
+            '\'' if escaping => out.push_str("''"),
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+/// The names of the arguments of `message` in order of appearance, nested ones included.
+pub fn arguments(message: &Message) -> Vec<String> {
+    fn walk(message: &Message, names: &mut Vec<String>) {
+        for node in message {
+            let (name, cases) = match node {
+                Node::Argument { name, .. } => (name, None),
+                Node::Plural { name, cases, .. } | Node::Select { name, cases } => {
+                    (name, Some(cases))
+                }
+                Node::Text(_) | Node::Pound => continue,
+            };
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+            for case in cases.into_iter().flatten() {
+                walk(&case.message, names);
+            }
+        }
+    }
+    let mut names = Vec::new();
+    walk(message, &mut names);
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+    fn argument(name: &str) -> Node {
+        Node::Argument {
+            name: name.to_string(),
+            format: None,
+            style: None,
+        }
+    }
+    fn case(selector: &str, message: Message) -> Case {
+        Case {
+            selector: selector.to_string(),
+            message,
+        }
+    }
+    fn error(src: &str) -> (usize, String) {
+        let e = parse(src, true).unwrap_err();
+        (e.offset, e.message)
+    }
+
+    #[test]
+    fn arguments_and_formats() {
+        assert_eq!(
+            parse("Hi {name}!", false).unwrap(),
+            vec![text("Hi "), argument("name"), text("!")]
+        );
+        assert_eq!(
+            parse("{ count , number , compact }", false).unwrap(),
+            vec![Node::Argument {
+                name: "count".into(),
+                format: Some("number".into()),
+                style: Some("compact".into()),
+            }]
+        );
+        assert_eq!(
+            parse("{day, date}", false).unwrap(),
+            vec![Node::Argument {
+                name: "day".into(),
+                format: Some("date".into()),
+                style: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn plurals_offsets_and_pound() {
+        assert_eq!(
+            parse("{n, plural, offset:1 =0{none} other{# others}}", false).unwrap(),
+            vec![Node::Plural {
+                name: "n".into(),
+                ordinal: false,
+                offset: Some(1),
+                cases: vec![
+                    case("=0", vec![text("none")]),
+                    case("other", vec![Node::Pound, text(" others")]),
+                ],
+            }]
+        );
+        assert!(matches!(
+            parse("{n, selectordinal, one{#st} other{#th}}", false).unwrap()[..],
+            [Node::Plural {
+                ordinal: true,
+                offset: None,
+                ..
+            }]
+        ));
+        // `#` is only the number inside a plural case
+        assert_eq!(
+            parse("# {n}", false).unwrap(),
+            vec![text("# "), argument("n")]
+        );
+        assert_eq!(
+            parse("{g, select, other{#}}", false).unwrap(),
+            vec![Node::Select {
+                name: "g".into(),
+                cases: vec![case("other", vec![text("#")])],
+            }]
+        );
+    }
+
+    #[test]
+    fn nested_messages() {
+        let message = parse(
+            "{n, plural, one{{who} and {g, select, other{#}}} other{{who} and {n}}} in {place}",
+            false,
+        )
+        .unwrap();
+        let Node::Plural { cases, .. } = &message[0] else {
+            panic!("{message:?}");
+        };
+        // a select inside a plural case still has the plural's `#`
+        assert_eq!(
+            cases[0].message,
+            vec![
+                argument("who"),
+                text(" and "),
+                Node::Select {
+                    name: "g".into(),
+                    cases: vec![case("other", vec![Node::Pound])],
+                },
+            ]
+        );
+        assert_eq!(message[1..], [text(" in "), argument("place")]);
+        assert_eq!(arguments(&message), ["n", "who", "g", "place"]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            parse("It''s '{name}'", true).unwrap(),
+            vec![text("It's {name}")]
+        );
+        // a lone apostrophe before anything but a brace is just text
+        assert_eq!(
+            parse("It's {name}", true).unwrap(),
+            vec![text("It's "), argument("name")]
+        );
+        assert_eq!(
+            parse("{n, plural, other{'#' #}}", true).unwrap()[0],
+            Node::Plural {
+                name: "n".into(),
+                ordinal: false,
+                offset: None,
+                cases: vec![case("other", vec![text("# "), Node::Pound])],
+            }
+        );
+        // quoted up to the end when the apostrophe is never closed
+        assert_eq!(parse("'{x} y", true).unwrap(), vec![text("{x} y")]);
+        // without `use-escaping` apostrophes quote nothing
+        assert_eq!(
+            parse("''{name}'", false).unwrap(),
+            vec![text("''"), argument("name"), text("'")]
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        let literal = "Use {braces}, it's #1";
+        let quoted = quote(literal, true, true).unwrap();
+        assert_eq!(quoted, "Use '{'braces'}', it''s '#'1");
+        let plural = format!("{{n, plural, other{{{quoted}}}}}");
+        let Node::Plural { cases, .. } = &parse(&plural, true).unwrap()[0] else {
+            panic!("{plural}");
+        };
+        assert_eq!(cases[0].message, vec![text(literal)]);
+        assert_eq!(quote("it's #1", false, false).as_deref(), Some("it's #1"));
+        assert_eq!(quote("#1", false, true), None);
+        assert_eq!(quote("{braces}", false, false), None);
+    }
+
+    #[test]
+    fn errors_and_their_offsets() {
+        assert_eq!(error("a}b"), (1, "unmatched `}`".into()));
+        assert_eq!(error("{n, plural, other{x}"), (20, "unclosed `{`".into()));
+        assert_eq!(error("{n, plural, other{x"), (19, "unclosed `{`".into()));
+        assert_eq!(
+            error("{name"),
+            (5, "expected `,` or `}` after `name`".into())
+        );
+        assert_eq!(
+            error("{n, plural, one{a} one{b} other{c}}"),
+            (22, "duplicate case `one`".into())
+        );
+        assert_eq!(
+            error("{g, select, male{he}}"),
+            (21, "missing the `other` case".into())
+        );
+        assert_eq!(
+            error("{n, plural, offset:x other{#}}"),
+            (20, "invalid offset `x`".into())
+        );
+        assert_eq!(error("{}"), (1, "expected an argument name".into()));
+        assert_eq!(
+            parse("'}", true).unwrap(),
+            vec![text("}")],
+            "a quoted brace is no error"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+
+use crate::callsite::{find_call_sites, referenced_keys};
+use crate::dart;
+use crate::icu::{self, Node};
+use crate::project::Project;
+use crate::utils::stringe;
+
+/// Writes `message` as a dart string literal, arguments becoming interpolations of the
+/// call's `args`. Returns None for plural and select messages, which have no literal form.
+fn dart_literal(message: &icu::Message, order: &[String], args: &[String]) -> Option<String> {
+    let identifier = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let mut literal = String::from("'");
+    for (i, node) in message.iter().enumerate() {
+        match node {
+            Node::Text(text) => {
+                for c in text.chars() {
+                    match c {
+                        '\\' => literal.push_str("\\\\"),
+                        '\'' => literal.push_str("\\'"),
+                        '$' => literal.push_str("\\$"),
+                        '\n' => literal.push_str("\\n"),
+                        '\r' => literal.push_str("\\r"),
+                        '\t' => literal.push_str("\\t"),
+                        c => literal.push(c),
+                    }
+                }
+            }
+            Node::Argument { name, .. } => {
+                let arg = args.get(order.iter().position(|n| n == name)?)?;
+                let glued = matches!(message.get(i + 1), Some(Node::Text(t))
+                    if t.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+                if identifier(arg) && !glued {
+                    literal.push('$');
+                    literal.push_str(arg);
+                } else {
+                    literal.push_str(&format!("${{{arg}}}"));
+                }
+            }
+            Node::Plural { .. } | Node::Select { .. } | Node::Pound => return None,
+        }
+    }
+    literal.push('\'');
+    Some(literal)
+}
+
+/// Removes `keys` and their metadata from every arb file.
+fn delete_keys(p: &Project, keys: &BTreeSet<String>) -> Result<(), String> {
+    for path in p.arb_files()? {
+        let arb = p.arb_file(path);
+        let removed = arb.remove_keys(keys)?;
+        if removed > 0 {
+            println!(
+                "[inline] Removed {removed} entries from {}",
+                arb.path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `arb-util inline`, replaces the call sites of `target`, a key or a dart file, with the
+/// template's message as a string literal. With `delete`, the inlined keys which are not
+/// referenced anywhere anymore are removed from every arb file.
+pub fn inline(p: &Project, target: &str, delete: bool) -> Result<(), String> {
+    let template = p.arb_file(p.arb_template_path()).read()?;
+    let (files, only_key) = if target.ends_with(".dart") {
+        let path = p.root_dir.join(target);
+        if !path.is_file() {
+            return Err(format!("no such dart file: {target}"));
+        }
+        (vec![path], None)
+    } else {
+        if !template.messages.contains_key(target) {
+            return Err(format!("no key `{target}` in the template arb file"));
+        }
+        (p.dart_files(), Some(target))
+    };
+
+    let mut inlined = BTreeSet::new();
+    for file in files {
+        let content = stringe(
+            format!("could not read {}", file.display()).as_str(),
+            std::fs::read_to_string(&file),
+        )?;
+        let display = file.strip_prefix(&p.root_dir).unwrap_or(&file).display();
+        let sites = match find_call_sites(p, &content) {
+            Ok(sites) => sites,
+            Err(e) => {
+                println!("[inline] Skipping {display}: {e}");
+                continue;
+            }
+        };
+        let mut new_content = content.clone();
+        for site in sites.iter().rev() {
+            if only_key.is_some_and(|k| k != site.key) {
+                continue;
+            }
+            // `localeName` and friends are not messages
+            let Some(message) = template.parse_message(&site.key, p.use_escaping) else {
+                continue;
+            };
+            let (line, _) = dart::line_col(&content, site.span.start);
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    println!(
+                        "[inline] {display}:{line}: `{}` is not a valid message, {e}",
+                        site.key
+                    );
+                    continue;
+                }
+            };
+            let order = template.placeholder_order(&site.key, p.use_escaping);
+            match dart_literal(&message, &order, &site.args) {
+                Some(literal) => {
+                    new_content.replace_range(site.span.clone(), &literal);
+                    inlined.insert(site.key.clone());
+                }
+                None => {
+                    println!(
+                        "[inline] {display}:{line}: `{}` is a plural or select message, left as is",
+                        site.key
+                    );
+                }
+            }
+        }
+        if new_content != content {
+            p.journal.write(&file, &new_content)?;
+            println!("[inline] Updated {display}");
+        }
+    }
+
+    if delete {
+        let mut keys: BTreeSet<String> = inlined;
+        keys.extend(only_key.map(String::from));
+        let referenced = referenced_keys(p)?;
+        for key in keys.iter().filter(|k| referenced.contains(*k)) {
+            println!("[inline] `{key}` is still used, keeping it");
+        }
+        keys.retain(|k| !referenced.contains(k));
+        delete_keys(p, &keys)?;
+    }
+    Ok(())
+}
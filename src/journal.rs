@@ -0,0 +1,438 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::utils::stringe;
+
+/// Every file arb-util writes goes through here, the content before and after each write
+/// is appended to `.dart_tool/arb-util/journal.jsonl` so `arb-util undo` can put it back.
+/// Writes hold an advisory lock on `.dart_tool/arb-util/lock`, shared by the tasks and any
+/// other arb-util process, and replace files atomically through a temporary file.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    dir: PathBuf,
+    /// The writes of a run, a command or one pass of a watching task, are undone together.
+    run: String,
+}
+
+/// Past this size the journal is trimmed to its newest runs.
+const MAX_JOURNAL_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    /// Empty for entries written before runs existed, each is a run of its own then.
+    #[serde(default)]
+    run: String,
+    time: String,
+    path: PathBuf,
+    /// None when the file did not exist.
+    before: Option<String>,
+    after: String,
+}
+
+impl Entry {
+    fn run(&self) -> &str {
+        if self.run.is_empty() {
+            &self.time
+        } else {
+            &self.run
+        }
+    }
+}
+
+fn new_run_id() -> String {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}-{}-{}",
+        humantime::format_rfc3339_millis(SystemTime::now()),
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Which runs `arb-util undo` reverts.
+pub enum Selection {
+    Last(usize),
+    Since(SystemTime),
+}
+
+impl Journal {
+    pub fn new(root_dir: &Path) -> Self {
+        Self {
+            dir: root_dir.join(".dart_tool").join("arb-util"),
+            run: new_run_id(),
+        }
+    }
+    /// Starts a new run, for the tasks watching files. Arb files get the journal when
+    /// made by [crate::project::Project::arb_file], so they are to be made after this.
+    pub fn new_run(&mut self) {
+        self.run = new_run_id();
+    }
+    fn path(&self) -> PathBuf {
+        self.dir.join("journal.jsonl")
+    }
+
+    /// Takes the lock, released when the returned file is dropped.
+    fn lock(&self) -> Result<std::fs::File, String> {
+        stringe(
+            "could not create the journal directory",
+            std::fs::create_dir_all(&self.dir),
+        )?;
+        let file = stringe(
+            "could not open the lock file",
+            std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(self.dir.join("lock")),
+        )?;
+        stringe("could not take the lock", file.lock())?;
+        Ok(file)
+    }
+
+    /// Writes `content` to `path`, recording what was there before.
+    pub fn write(&self, path: &Path, content: &str) -> Result<(), String> {
+        let _lock = self.lock()?;
+        let before = std::fs::read_to_string(path).ok();
+        self.write_locked(path, before, content)
+    }
+
+    /// Writes `content` to `path` only if it still holds `expected`, what `content` was
+    /// made from, None meaning the file did not exist. Returns whether it did.
+    pub fn replace(
+        &self,
+        path: &Path,
+        expected: Option<&str>,
+        content: &str,
+    ) -> Result<bool, String> {
+        self.replace_all(&[(path, expected, content)])
+    }
+
+    /// Like [Journal::replace] for several files at once, either all of them are written
+    /// or, if any of them changed, none.
+    pub fn replace_all(&self, changes: &[(&Path, Option<&str>, &str)]) -> Result<bool, String> {
+        let _lock = self.lock()?;
+        let mut befores = Vec::new();
+        for (path, expected, _) in changes {
+            let before = std::fs::read_to_string(path).ok();
+            if before.as_deref() != *expected {
+                return Ok(false);
+            }
+            befores.push(before);
+        }
+        for ((path, _, content), before) in changes.iter().zip(befores) {
+            self.write_locked(path, before, content)?;
+        }
+        Ok(true)
+    }
+
+    fn write_locked(
+        &self,
+        path: &Path,
+        before: Option<String>,
+        content: &str,
+    ) -> Result<(), String> {
+        if before.as_deref() == Some(content) {
+            return Ok(());
+        }
+        write_atomic(path, content)?;
+        self.record(Entry {
+            run: self.run.clone(),
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            path: path.to_path_buf(),
+            before,
+            after: content.to_string(),
+        })
+    }
+
+    fn record(&self, entry: Entry) -> Result<(), String> {
+        stringe(
+            "could not create the journal directory",
+            std::fs::create_dir_all(&self.dir),
+        )?;
+        let mut line = stringe(
+            "could not serialize journal entry",
+            serde_json::to_string(&entry),
+        )?;
+        line.push('\n');
+        let mut file = stringe(
+            "could not open the journal",
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path()),
+        )?;
+        stringe(
+            "could not write to the journal",
+            file.write_all(line.as_bytes()),
+        )?;
+        if file.metadata().is_ok_and(|m| m.len() > MAX_JOURNAL_BYTES) {
+            self.trim(MAX_JOURNAL_BYTES)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest runs, down to half of `max` so this does not happen on every write.
+    /// The newest run is always kept.
+    fn trim(&self, max: u64) -> Result<(), String> {
+        let entries = self.entries()?;
+        let mut lines = Vec::new();
+        for entry in &entries {
+            lines.push(stringe(
+                "could not serialize journal entry",
+                serde_json::to_string(entry),
+            )?);
+        }
+        let mut kept_runs = BTreeSet::new();
+        let mut size = 0;
+        for (entry, line) in entries.iter().zip(&lines).rev() {
+            size += line.len() as u64 + 1;
+            if size > max / 2 && !kept_runs.is_empty() && !kept_runs.contains(entry.run()) {
+                break;
+            }
+            kept_runs.insert(entry.run());
+        }
+        let mut remaining = String::new();
+        for (entry, line) in entries.iter().zip(&lines) {
+            if kept_runs.contains(entry.run()) {
+                remaining.push_str(line);
+                remaining.push('\n');
+            }
+        }
+        write_atomic(&self.path(), &remaining)
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>, String> {
+        let content = match std::fs::read_to_string(self.path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("could not read the journal: {e}")),
+        };
+        content
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| stringe("could not decode journal entry", serde_json::from_str(l)))
+            .collect()
+    }
+
+    /// Restores the files as they were before the selected runs, newest write first.
+    /// Nothing is restored if any of the files changed since arb-util wrote it.
+    pub fn undo(&self, selection: Selection) -> Result<(), String> {
+        let _lock = self.lock()?;
+        let entries = self.entries()?;
+        let runs: BTreeSet<String> = match selection {
+            Selection::Last(n) => {
+                // in the order they ended, the tasks' runs may interleave
+                let mut runs: Vec<&str> = Vec::new();
+                for entry in &entries {
+                    runs.retain(|r| *r != entry.run());
+                    runs.push(entry.run());
+                }
+                runs.iter().rev().take(n).map(|r| r.to_string()).collect()
+            }
+            // whole runs, even if they started before `since`
+            Selection::Since(since) => entries
+                .iter()
+                .filter(|e| humantime::parse_rfc3339(&e.time).is_ok_and(|time| time >= since))
+                .map(|e| e.run().to_string())
+                .collect(),
+        };
+        let (undone, entries): (Vec<Entry>, Vec<Entry>) =
+            entries.into_iter().partition(|e| runs.contains(e.run()));
+        if undone.is_empty() {
+            println!("[undo] Nothing to undo");
+            return Ok(());
+        }
+
+        // check everything first, following what each file holds as writes are undone
+        let mut states: Vec<(PathBuf, Option<String>)> = Vec::new();
+        for entry in undone.iter().rev() {
+            let current = match states.iter().find(|(p, _)| *p == entry.path) {
+                Some((_, state)) => state.clone(),
+                None => std::fs::read_to_string(&entry.path).ok(),
+            };
+            if current.as_deref() != Some(entry.after.as_str()) {
+                return Err(format!(
+                    "{} changed since arb-util wrote it at {}, not undoing anything",
+                    entry.path.display(),
+                    entry.time
+                ));
+            }
+            states.retain(|(p, _)| *p != entry.path);
+            states.push((entry.path.clone(), entry.before.clone()));
+        }
+
+        for (path, state) in states {
+            match state {
+                Some(content) => write_atomic(&path, &content)?,
+                None => stringe(
+                    format!("could not remove {}", path.display()).as_str(),
+                    std::fs::remove_file(&path),
+                )?,
+            }
+            println!("[undo] Restored {}", path.display());
+        }
+
+        let mut remaining = String::new();
+        for entry in entries {
+            remaining.push_str(&stringe(
+                "could not serialize journal entry",
+                serde_json::to_string(&entry),
+            )?);
+            remaining.push('\n');
+        }
+        write_atomic(&self.path(), &remaining)
+    }
+}
+
+/// Writes to a temporary file next to `path` then renames it over `path`, so readers
+/// see either the old or the new content, never half of it.
+fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let temp = path.with_file_name(format!(".{name}.arb-util.tmp"));
+    let result = std::fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    stringe(
+        format!("could not write {}", path.display()).as_str(),
+        result,
+    )
+}
+
+/// Parses `--since`, either a duration ago like `15m` or `2h`, or a UTC date like
+/// `2026-01-31 18:00:00`, the journal times being UTC.
+pub fn parse_since(since: &str) -> Result<SystemTime, String> {
+    if let Ok(duration) = humantime::parse_duration(since) {
+        return Ok(SystemTime::now() - duration);
+    }
+    stringe(
+        "--since expects a duration like `15m` or a UTC date like `2026-01-31 18:00:00`",
+        humantime::parse_rfc3339_weak(since),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A journal in an empty directory of its own, with the path of a file in it.
+    fn journal(name: &str) -> (Journal, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("arb-util-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        (Journal::new(&dir), dir.join("app_en.arb"))
+    }
+
+    fn read(path: &Path) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn undo_goes_back_one_run_at_a_time() {
+        let (mut journal, file) = journal("undo-runs");
+        let other = file.with_file_name("page.dart");
+        journal.write(&file, "1").unwrap();
+        journal.new_run();
+        journal.write(&file, "2").unwrap();
+        journal.write(&other, "a").unwrap();
+        journal.write(&file, "3").unwrap();
+        journal.new_run();
+        journal.write(&file, "4").unwrap();
+
+        journal.undo(Selection::Last(1)).unwrap();
+        assert_eq!(read(&file).as_deref(), Some("3"));
+        // the whole run, both files, back to before its first write
+        journal.undo(Selection::Last(1)).unwrap();
+        assert_eq!(read(&file).as_deref(), Some("1"));
+        assert_eq!(read(&other), None);
+        journal.undo(Selection::Last(5)).unwrap();
+        assert_eq!(read(&file), None);
+        assert!(journal.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn undo_refuses_files_changed_since() {
+        let (mut journal, file) = journal("undo-changed");
+        let other = file.with_file_name("page.dart");
+        journal.write(&file, "1").unwrap();
+        journal.new_run();
+        journal.write(&other, "a").unwrap();
+        journal.write(&file, "2").unwrap();
+        std::fs::write(&file, "edited").unwrap();
+
+        assert!(journal.undo(Selection::Last(1)).is_err());
+        assert_eq!(read(&file).as_deref(), Some("edited"));
+        assert_eq!(read(&other).as_deref(), Some("a"));
+        assert_eq!(journal.entries().unwrap().len(), 3);
+
+        std::fs::write(&file, "2").unwrap();
+        journal.undo(Selection::Last(1)).unwrap();
+        assert_eq!(read(&file).as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn replace_all_writes_all_or_nothing() {
+        let (journal, file) = journal("replace-all");
+        let other = file.with_file_name("page.dart");
+        journal.write(&file, "1").unwrap();
+        let stale = [
+            (file.as_path(), Some("1"), "2"),
+            (other.as_path(), Some("x"), "b"),
+        ];
+        assert!(!journal.replace_all(&stale).unwrap());
+        assert_eq!(read(&file).as_deref(), Some("1"));
+        assert_eq!(read(&other), None);
+        let fresh = [
+            (file.as_path(), Some("1"), "2"),
+            (other.as_path(), None, "b"),
+        ];
+        assert!(journal.replace_all(&fresh).unwrap());
+        assert_eq!(read(&file).as_deref(), Some("2"));
+        assert_eq!(read(&other).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn trim_keeps_the_newest_runs() {
+        let (mut journal, file) = journal("trim");
+        let mut runs = Vec::new();
+        for i in 0..10 {
+            journal.new_run();
+            runs.push(journal.run.clone());
+            journal.write(&file, &i.to_string().repeat(100)).unwrap();
+            journal.write(&file, &i.to_string().repeat(50)).unwrap();
+        }
+        let size = std::fs::metadata(journal.path()).unwrap().len();
+        journal.trim(size / 2).unwrap();
+        let kept: BTreeSet<String> = journal
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| e.run().to_string())
+            .collect();
+        // whole runs, the newest ones, within a quarter of the size
+        assert!(kept.len() > 1 && kept.len() < 4, "{kept:?}");
+        assert!(
+            kept.iter()
+                .all(|r| runs[runs.len() - kept.len()..].contains(r))
+        );
+        assert!(std::fs::metadata(journal.path()).unwrap().len() <= size / 4);
+
+        // the newest run stays even when it is over the limit alone
+        journal.trim(1).unwrap();
+        let kept: Vec<String> = journal
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| e.run().to_string())
+            .collect();
+        assert_eq!(kept, [runs[9].clone(), runs[9].clone()]);
+    }
+}
@@ -0,0 +1,234 @@
+use regex::Regex;
+use std::ops::Range;
+
+use crate::dart::{self, StringPart, Token, TokenKind};
+use crate::diagnostic::{self, Diagnostic, Format, Severity};
+use crate::extractor;
+use crate::project::{Marker, Project};
+use crate::utils::stringe;
+
+/// Comments with this tag silence the linter on their line, or on the next one
+/// when alone on theirs.
+const IGNORE_TAG: &str = "arb-util:ignore";
+
+/// A string literal, or adjacent ones, users get to see and nobody localized.
+struct Unmarked {
+    span: Range<usize>,
+    text: String,
+    /// `Text` or `title:`, what made it a UI string.
+    position: String,
+}
+
+/// Tells if a comment holding `tag` is attached to `span`, see [`dart::attached_comments`].
+fn tagged(content: &str, tokens: &[Token], span: &Range<usize>, tag: &str) -> bool {
+    dart::attached_comments(content, tokens, span)
+        .iter()
+        .any(|t| t.text(content).contains(tag))
+}
+
+/// Tells if the extractor sees `span` as marked by a `// {tag}` comment, which has to follow it
+/// on the line it starts on.
+fn comment_marked(content: &str, tokens: &[Token], span: &Range<usize>, tag: &str) -> bool {
+    tokens
+        .iter()
+        .filter(|t| t.is_comment() && t.span.start >= span.end)
+        .take_while(|t| !content[span.start..t.span.start].contains('\n'))
+        .any(|t| extractor::marker_comment(t.text(content), tag).is_some())
+}
+
+fn find_unmarked(p: &Project, content: &str, tokens: &[Token], allow: &[Regex]) -> Vec<Unmarked> {
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.is_comment()).collect();
+    let lint = &p.config.lint;
+    let mut unmarked = Vec::new();
+
+    let mut i = 0;
+    while i < code.len() {
+        if !matches!(code[i].kind, TokenKind::Str(_)) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut text = String::new();
+        while let Some(TokenKind::Str(s)) = code.get(i).map(|t| &t.kind) {
+            for part in &s.parts {
+                match part {
+                    StringPart::Text(t) => text.push_str(t),
+                    StringPart::Interpolation { expr, .. } => {
+                        text.push_str(&format!("${{{expr}}}"))
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let before = |n: usize| start.checked_sub(n).map(|j| code[j]);
+        let widget = before(2)
+            .filter(|_| before(1).is_some_and(|t| t.is_punct(content, "(")))
+            .filter(|t| lint.widgets.iter().any(|w| t.is_ident(content, w)));
+        // a named argument, not a map entry or the end of a conditional
+        let argument = before(2)
+            .filter(|_| before(1).is_some_and(|t| t.is_punct(content, ":")))
+            .filter(|_| {
+                before(3).is_some_and(|t| t.is_punct(content, "(") || t.is_punct(content, ","))
+            })
+            .filter(|t| lint.arguments.iter().any(|a| t.is_ident(content, a)));
+        let position = match (widget, argument) {
+            (Some(w), _) => w.text(content).to_string(),
+            (_, Some(a)) => format!("{}:", a.text(content)),
+            _ => continue,
+        };
+
+        let span = code[start].span.start..code[i - 1].span.end;
+        let words = text
+            .split("${")
+            .enumerate()
+            .map(|(n, s)| {
+                if n == 0 {
+                    s
+                } else {
+                    s.split_once('}').map_or("", |(_, s)| s)
+                }
+            })
+            .any(|s| s.chars().any(char::is_alphabetic));
+        let allowed = allow.iter().any(|r| r.is_match(&text));
+        let marked = match &p.config.marker {
+            Marker::Comment(tag) => comment_marked(content, tokens, &span, tag),
+            _ => false,
+        };
+        if words && !allowed && !marked && !tagged(content, tokens, &span, IGNORE_TAG) {
+            unmarked.push(Unmarked {
+                span,
+                text,
+                position,
+            });
+        }
+    }
+    unmarked
+}
+
+/// Marks `strings` with the configured marker.
+fn mark(p: &Project, content: &mut String, tokens: &[Token], strings: &[Unmarked]) {
+    let mut insertions: Vec<(usize, String)> = Vec::new();
+    for s in strings {
+        match &p.config.marker {
+            Marker::Prefix(name) | Marker::Function(name) => {
+                insertions.push((s.span.start, format!("{name}(")));
+                insertions.push((s.span.end, String::from(")")));
+            }
+            Marker::Comment(tag) => {
+                // before any comment already ending the line
+                let line_end = content[s.span.end..]
+                    .find('\n')
+                    .map_or(content.len(), |i| s.span.end + i);
+                let at = tokens
+                    .iter()
+                    .find(|t| t.is_comment() && t.span.start >= s.span.end)
+                    .filter(|t| t.span.start < line_end)
+                    .map_or(line_end, |t| content[..t.span.start].trim_end().len());
+                if !insertions.iter().any(|(i, _)| *i == at) {
+                    insertions.push((at, format!(" // {tag}")));
+                }
+            }
+        }
+    }
+    // from the end of the file so offsets stay valid
+    insertions.sort_by_key(|(at, _)| *at);
+    for (at, text) in insertions.into_iter().rev() {
+        content.insert_str(at, &text);
+    }
+}
+
+/// `arb-util lint`, reports the strings of `lib/` shown in the UI which are neither
+/// marked nor localized. With `fix` they get marked for the extractor instead.
+/// Returns whether any string was left unmarked.
+pub fn lint(p: &Project, fix: bool, format: Format) -> Result<bool, String> {
+    let allow = p
+        .config
+        .lint
+        .allow
+        .iter()
+        .map(|a| {
+            stringe(
+                "invalid regex in lint.allow",
+                Regex::new(&format!("^(?:{a})$")),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut diagnostics = Vec::new();
+    let mut found = false;
+    for path in p.dart_files() {
+        let mut content = stringe(
+            format!("could not read {}", path.display()).as_str(),
+            std::fs::read_to_string(&path),
+        )?;
+        let display = path.strip_prefix(&p.root_dir).unwrap_or(&path).display();
+        let tokens = match dart::tokenize(&content) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    "invalid-dart",
+                    &p.root_dir,
+                    &path,
+                    format!("skipped, could not parse it: {e}"),
+                ));
+                continue;
+            }
+        };
+        let mut unmarked = find_unmarked(p, &content, &tokens, &allow);
+        if unmarked.is_empty() {
+            continue;
+        }
+        if fix {
+            mark(p, &mut content, &tokens, &unmarked);
+            p.journal.write(&path, &content)?;
+            if format.is_text() {
+                println!("[lint] Marked {} strings in {display}", unmarked.len());
+            }
+            // only what the markers did not take care of is left to report
+            unmarked = match dart::tokenize(&content) {
+                Ok(tokens) => find_unmarked(p, &content, &tokens, &allow),
+                Err(_) => Vec::new(),
+            };
+        }
+        for s in &unmarked {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "unmarked-string",
+                    &p.root_dir,
+                    &path,
+                    format!("unmarked string {:?} in {}", s.text, s.position),
+                )
+                .at(dart::line_col(&content, s.span.start)),
+            );
+        }
+        found |= !unmarked.is_empty();
+    }
+    diagnostic::report(format, "lint", &diagnostics);
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comment_markers_follow_the_extractor() {
+        let marked = |src: &str| {
+            let tokens = dart::tokenize(src).unwrap();
+            let string = tokens
+                .iter()
+                .find(|t| matches!(t.kind, TokenKind::Str(_)))
+                .unwrap();
+            comment_marked(src, &tokens, &string.span, "l10n")
+        };
+        assert!(marked("Text('Hi'), // l10n"));
+        assert!(marked("Text('Hi'), // l10n: greeting"));
+        assert!(!marked("// l10n\nText('Hi'),"));
+        assert!(!marked("Text('Hi'), // see l10n docs"));
+        assert!(!marked("Text('Hi'), // l10ns"));
+        assert!(!marked("Text('Hi'),\n// l10n"));
+    }
+}
@@ -1,17 +1,146 @@
 mod arb;
+mod callsite;
+mod checker;
+mod dart;
+mod diagnostic;
 mod extractor;
+mod icu;
+mod inliner;
+mod journal;
+mod linter;
+mod merger;
 mod project;
+mod renamer;
 mod syncer;
 mod translator;
+mod unused;
 mod utils;
 mod watcher;
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
 
+/// Extracts, syncs and translates the arb files of a flutter project,
+/// watching for changes when run without a command.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate every arb file against the template, exit with 1 if anything is wrong
+    Check {
+        /// How to print the problems
+        #[arg(long, value_enum, default_value_t)]
+        format: diagnostic::Format,
+    },
+    /// Extract the marked strings once and exit
+    Extract {
+        /// Print a diff of the changes instead of writing them, and exit with 1 if there are any
+        #[arg(long)]
+        dry_run: bool,
+        /// How to print the problems met on the way
+        #[arg(long, value_enum, default_value_t)]
+        format: diagnostic::Format,
+    },
+    /// Replace the call sites of a key, or of all keys in a dart file, with the message literal
+    Inline {
+        /// A template key, or a dart file path relative to the project root
+        target: String,
+        /// Also remove the inlined keys no longer used anywhere from every arb file
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Report the strings shown in the UI that are neither marked nor localized, exit with 1 if any
+    Lint {
+        /// Mark them for the extractor instead
+        #[arg(long)]
+        fix: bool,
+        /// How to print the strings found
+        #[arg(long, value_enum, default_value_t)]
+        format: diagnostic::Format,
+    },
+    /// Merge the template keys holding the same message, list those differing by case and punctuation
+    MergeDuplicates {
+        /// Only list the duplicates and the key each group would be merged into
+        #[arg(long)]
+        dry_run: bool,
+        /// Merge the group of KEY into it, even if their messages differ in case or punctuation
+        #[arg(long, value_name = "KEY")]
+        into: Option<String>,
+    },
+    /// Rename a key in every arb file and at every dart call site
+    RenameKey { old: String, new: String },
+    /// Report the template keys no call site references, exit with 1 if any
+    Unused {
+        /// Remove them and their metadata from every arb file instead
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Put back the files as they were before arb-util's last runs
+    Undo {
+        /// Undo the last N runs, a command or one pass of a watching task, 1 by default
+        #[arg(long, conflicts_with = "since")]
+        last: Option<usize>,
+        /// Undo the runs which wrote since TIME, a duration ago like `15m` or a UTC date like `2026-01-31 18:00:00`
+        #[arg(long)]
+        since: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
+    let cli = Cli::parse();
     dotenv().ok();
-    println!("arb-util");
     let p = project::Project::load()?;
+
+    match cli.command {
+        None => {}
+        Some(Command::Check { format }) => {
+            if checker::check(&p, format)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Extract { dry_run, format }) => {
+            if extractor::extract_once(&p, dry_run, format)? && dry_run {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Inline { target, delete }) => {
+            return inliner::inline(&p, &target, delete);
+        }
+        Some(Command::Lint { fix, format }) => {
+            if linter::lint(&p, fix, format)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::MergeDuplicates { dry_run, into }) => {
+            return merger::merge_duplicates(&p, dry_run, into.as_deref());
+        }
+        Some(Command::RenameKey { old, new }) => {
+            return renamer::rename_key(&p, &old, &new);
+        }
+        Some(Command::Unused { prune }) => {
+            if unused::unused(&p, prune)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Undo { last, since }) => {
+            let selection = match since {
+                Some(since) => journal::Selection::Since(journal::parse_since(&since)?),
+                None => journal::Selection::Last(last.unwrap_or(1)),
+            };
+            return p.journal.undo(selection);
+        }
+    }
+
+    println!("arb-util");
     println!("{p:#?}");
 
     let extractor_handle = tokio::spawn(extractor::run(p.clone()));
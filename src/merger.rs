@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use crate::arb::ArbDocument;
+use crate::callsite::find_call_sites;
+use crate::project::Project;
+use crate::utils::stringe;
+
+/// What equivalent messages have in common: the words, lowercased, without punctuation.
+fn normalized(message: &str) -> String {
+    message
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '{' || *c == '}')
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Tells if a locale holds an actual translation of `key`, not a `#` one left for the translator.
+fn translated(content: &ArbDocument, key: &str) -> bool {
+    content
+        .messages
+        .get(key)
+        .is_some_and(|m| !m.starts_with('#'))
+}
+
+/// `arb-util merge-duplicates`, finds the template messages which are the same but for
+/// case and punctuation. The keys of identical messages are merged into one: the one with
+/// the most call sites, then the shortest. Groups whose messages differ are only listed, as
+/// picking one would change what users see, unless `into` is one of their keys. Call sites
+/// and every locale arb file are rewritten to use it, with `dry_run` the groups are only listed.
+pub fn merge_duplicates(p: &Project, dry_run: bool, into: Option<&str>) -> Result<(), String> {
+    let template = p.arb_file(p.arb_template_path()).read()?;
+
+    // the placeholders must line up too, or the call site arguments would get mixed up
+    let mut groups: BTreeMap<(String, Vec<String>), Vec<String>> = BTreeMap::new();
+    for (key, message) in &template.messages {
+        let order = template.placeholder_order(key, p.use_escaping);
+        groups
+            .entry((normalized(message), order))
+            .or_default()
+            .push(key.clone());
+    }
+    groups.retain(|_, keys| keys.len() > 1);
+    if let Some(into) = into
+        && !groups.values().flatten().any(|k| k == into)
+    {
+        return Err(format!("`{into}` has no duplicate in the template"));
+    }
+    if groups.is_empty() {
+        println!("[merge] No duplicate messages");
+        return Ok(());
+    }
+
+    let mut files = Vec::new();
+    let mut uses: BTreeMap<String, usize> = BTreeMap::new();
+    for file in p.dart_files() {
+        let display = file.strip_prefix(&p.root_dir).unwrap_or(&file).display();
+        let content = stringe(
+            format!("could not read {display}").as_str(),
+            std::fs::read_to_string(&file),
+        )?;
+        let sites = find_call_sites(p, &content).map_err(|e| format!("{display}: {e}"))?;
+        for site in &sites {
+            *uses.entry(site.key.clone()).or_default() += 1;
+        }
+        files.push((file, content, sites));
+    }
+
+    let most_used = |keys: &[String]| {
+        keys.iter()
+            .min_by_key(|k| {
+                let n = uses.get(*k).copied().unwrap_or(0);
+                (std::cmp::Reverse(n), k.len(), (*k).clone())
+            })
+            .unwrap()
+            .clone()
+    };
+    // every duplicate key with the key it merges into
+    let mut merged: BTreeMap<String, String> = BTreeMap::new();
+    for keys in groups.into_values() {
+        let into = into.filter(|into| keys.iter().any(|k| k == into));
+        let mut same: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for key in &keys {
+            same.entry(template.messages[key].as_str())
+                .or_default()
+                .push(key.clone());
+        }
+        let mut merges: Vec<(String, Vec<String>)> = same
+            .into_values()
+            .map(|same| match into {
+                Some(into) if same.iter().any(|k| k == into) => (into.to_string(), same),
+                _ => (most_used(&same), same),
+            })
+            .collect();
+        if merges.len() > 1 {
+            match into {
+                Some(into) => merges = vec![(into.to_string(), keys)],
+                None => {
+                    println!(
+                        "[merge] Differing in case or punctuation, left apart, pick the one to keep with `--into <key>`:"
+                    );
+                    for (canonical, _) in &merges {
+                        println!("[merge]   {canonical}: {:?}", template.messages[canonical]);
+                    }
+                }
+            }
+        }
+        for (canonical, keys) in merges.into_iter().filter(|(_, keys)| keys.len() > 1) {
+            println!("[merge] {canonical}: {:?}", template.messages[&canonical]);
+            for key in keys.into_iter().filter(|k| *k != canonical) {
+                println!("[merge]   <- {key}: {:?}", template.messages[&key]);
+                merged.insert(key, canonical.clone());
+            }
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    for path in p.arb_files()? {
+        let arb = p.arb_file(path);
+        let changed = arb.update(|content| {
+            let mut changed = false;
+            for (key, canonical) in &merged {
+                let (message, metadata) = content.remove(key);
+                changed |= message.is_some() || metadata.is_some();
+                // keep a translation the canonical key lacks
+                if !translated(content, canonical)
+                    && let Some(message) = message.filter(|m| !m.starts_with('#'))
+                {
+                    content.messages.insert(canonical.clone(), message);
+                }
+                // and the description, for the template
+                if let Some(description) = metadata.and_then(|m| m.description) {
+                    content
+                        .metadata
+                        .entry(canonical.clone())
+                        .or_default()
+                        .description
+                        .get_or_insert(description);
+                }
+            }
+            Ok(changed)
+        })?;
+        if !changed {
+            continue;
+        }
+        println!("[merge] Updated {}", arb.path.display());
+    }
+    for (file, mut content, sites) in files {
+        let mut changed = 0;
+        for site in sites.iter().rev() {
+            if let Some(canonical) = merged.get(&site.key) {
+                content.replace_range(site.key_span.clone(), canonical);
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            p.journal.write(&file, &content)?;
+            println!(
+                "[merge] Rewrote {changed} call sites in {}",
+                file.strip_prefix(&p.root_dir).unwrap_or(&file).display()
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,5 +1,10 @@
-use super::utils::stringe;
-use std::path::PathBuf;
+use super::{arb::ArbFile, journal::Journal, utils::stringe};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Files generated by code generators, extracting from them is pointless
+/// since they get overwritten on the next build.
+const GENERATED_SUFFIXES: [&str; 4] = [".g.dart", ".freezed.dart", ".gr.dart", ".mocks.dart"];
 
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -7,12 +12,188 @@ pub struct L10NYaml {
     arb_dir: String,
     template_arb_file: String,
     output_localization_file: String,
+    output_dir: Option<String>,
+    output_class: Option<String>,
+    nullable_getter: Option<bool>,
+    use_escaping: Option<bool>,
 }
 #[derive(Debug, serde::Deserialize)]
 pub struct PubSpec {
     name: String,
 }
 
+/// arb-util's own settings, read from an optional `arb-util.yaml` at the project root.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Config {
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub marker: Marker,
+    /// `_p(count, one: "1 item", other: "$count items")`
+    pub plural_marker: String,
+    /// `_s(gender, male: "he", female: "she", other: "they")`
+    pub select_marker: String,
+    /// What marked strings are replaced with, `{class}`, `{key}` and `{args}` are
+    /// substituted, `{args}` being empty for messages without placeholders. Both `{key}`
+    /// and `{args}` are required.
+    pub call_site: Option<String>,
+    /// Used instead of `call-site` where it needs a `context` and there is none in scope,
+    /// such strings are left marked and reported when this is not set.
+    pub context_free_call_site: Option<String>,
+    pub keys: KeyNaming,
+    /// `format` and `optionalParameters` of typed placeholders, by type,
+    /// on top of the defaults from [Project::placeholder_format].
+    pub placeholder_formats: HashMap<String, PlaceholderFormat>,
+    pub lint: Lint,
+    /// Where keys new to an arb file are written, the others stay where they are.
+    pub new_key_position: NewKeyPosition,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NewKeyPosition {
+    /// At the end of the file.
+    #[default]
+    End,
+    /// Before the first key coming after it alphabetically, for files kept sorted.
+    Sorted,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum PlaceholderFormat {
+    /// `int: compact`
+    Format(String),
+    /// `double: {format: currency, optional-parameters: {decimalDigits: 2}}`
+    #[serde(rename_all = "kebab-case")]
+    Full {
+        format: String,
+        optional_parameters: Option<serde_json::Value>,
+    },
+}
+
+/// Where `arb-util lint` looks for strings users get to see.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Lint {
+    /// Widgets whose first positional argument is shown as is: `Text("...")`.
+    pub widgets: Vec<String>,
+    /// Named arguments holding UI text: `title: "..."`.
+    pub arguments: Vec<String>,
+    /// Regexes of strings that are fine left as they are, matched against the whole string.
+    pub allow: Vec<String>,
+}
+
+impl Default for Lint {
+    fn default() -> Self {
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect();
+        Self {
+            widgets: strings(&["Text", "SelectableText"]),
+            arguments: strings(&[
+                "title",
+                "subtitle",
+                "label",
+                "labelText",
+                "hintText",
+                "helperText",
+                "errorText",
+                "counterText",
+                "prefixText",
+                "suffixText",
+                "tooltip",
+                "message",
+                "semanticLabel",
+                "semanticsLabel",
+                "text",
+            ]),
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// How keys are made up for extracted strings.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct KeyNaming {
+    pub strategy: KeyStrategy,
+    /// Maximum length of the slug part of `slug` and `*-prefixed` keys.
+    pub max_length: usize,
+    /// Turn non-ASCII letters into their closest ASCII spelling, `é` into `e`. Otherwise
+    /// keys with such letters fall back to a hash, dart identifiers being ASCII only.
+    pub transliterate: bool,
+    /// What to do when a string gets a key that already holds a different message.
+    pub collision: Collision,
+}
+
+impl Default for KeyNaming {
+    fn default() -> Self {
+        Self {
+            strategy: KeyStrategy::Sentence,
+            max_length: 32,
+            transliterate: false,
+            collision: Collision::Suffix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collision {
+    /// Use the first free `key2`, `key3`... instead.
+    Suffix,
+    /// Leave the string marked and report it.
+    Refuse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyStrategy {
+    /// The whole sentence in camelCase, punctuation spelled out: `saveExclamationMark`.
+    Sentence,
+    /// The words of the sentence in camelCase, cut to `max-length`: `save`.
+    Slug,
+    /// The slug prefixed with the file name: `loginPage_signIn` for `login_page.dart`.
+    FilePrefixed,
+    /// The slug prefixed with the enclosing class, or the file name outside classes.
+    ClassPrefixed,
+    /// A stable short hash of the message: `msg1f2e3d4c`.
+    Hash,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            marker: Marker::default(),
+            plural_marker: String::from("_p"),
+            select_marker: String::from("_s"),
+            call_site: None,
+            context_free_call_site: None,
+            keys: KeyNaming::default(),
+            placeholder_formats: HashMap::new(),
+            lint: Lint::default(),
+            new_key_position: NewKeyPosition::default(),
+        }
+    }
+}
+
+/// How strings to extract are marked in dart files.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Marker {
+    /// `_("...")`, the prefix is glued to the parenthesis and must not be a member access.
+    Prefix(String),
+    /// `tr("...")` or `I18n.tr("...")`, a possibly qualified function call.
+    Function(String),
+    /// `"..." // l10n`, every string on the line before the comment is extracted
+    /// and the comment removed.
+    Comment(String),
+}
+
+impl Default for Marker {
+    fn default() -> Self {
+        Self::Prefix(String::from("_"))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Project {
     pub name: String,
@@ -20,11 +201,100 @@ pub struct Project {
     pub l10n_dir: PathBuf,
     pub arb_template: String,
     pub localizations_file: String,
+    /// Where `flutter gen-l10n` writes the generated dart files, relative to `root_dir`.
+    pub output_dir: PathBuf,
+    /// The generated localizations class, `output-class` in l10n.yaml.
+    pub output_class: String,
+    pub nullable_getter: bool,
+    /// Whether `'` quotes braces in messages, see [crate::icu::parse].
+    pub use_escaping: bool,
+    pub config: Config,
+    pub journal: Journal,
 }
 impl Project {
     pub fn arb_template_path(&self) -> PathBuf {
         self.root_dir.join(&self.l10n_dir).join(&self.arb_template)
     }
+    /// The call-site template from arb-util.yaml, or `{class}.of(context)!.{key}{args}`
+    /// without the `!` when l10n.yaml sets `nullable-getter: false`.
+    pub fn call_site_template(&self) -> String {
+        match &self.config.call_site {
+            Some(template) => template.clone(),
+            None if self.nullable_getter => String::from("{class}.of(context)!.{key}{args}"),
+            None => String::from("{class}.of(context).{key}{args}"),
+        }
+    }
+    /// The format for placeholders of type `kind`, `decimalPattern` for numbers and
+    /// `yMMMd` for dates unless arb-util.yaml says otherwise.
+    pub fn placeholder_format(&self, kind: &str) -> Option<PlaceholderFormat> {
+        if let Some(format) = self.config.placeholder_formats.get(kind) {
+            return Some(format.clone());
+        }
+        match kind {
+            "int" | "double" | "num" => {
+                Some(PlaceholderFormat::Format(String::from("decimalPattern")))
+            }
+            "DateTime" => Some(PlaceholderFormat::Format(String::from("yMMMd"))),
+            _ => None,
+        }
+    }
+    /// Tells if `path` is a dart file the extractor should look at, that is, one that
+    /// is neither generated by build_runner and friends nor by `flutter gen-l10n`.
+    pub fn is_source_file(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        name.ends_with(".dart")
+            && !GENERATED_SUFFIXES.iter().any(|s| name.ends_with(s))
+            && !path.starts_with(self.root_dir.join(&self.output_dir))
+    }
+    /// Tells if `path` is ignored by the `.gitignore` (or `.ignore`) files of its directory
+    /// and the ones above it up to the project root, as [Project::dart_files] sees it.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+        // the deepest file with an opinion wins, like git does
+        for dir in path.ancestors().skip(1) {
+            for name in [".ignore", ".gitignore"] {
+                let (matcher, _) = ignore::gitignore::Gitignore::new(dir.join(name));
+                match matcher.matched_path_or_any_parents(path, is_dir) {
+                    ignore::Match::Ignore(_) => return true,
+                    ignore::Match::Whitelist(_) => return false,
+                    ignore::Match::None => {}
+                }
+            }
+            if dir == self.root_dir {
+                break;
+            }
+        }
+        false
+    }
+    pub fn arb_file(&self, path: PathBuf) -> ArbFile {
+        ArbFile::new(path, self.journal.clone(), self.config.new_key_position)
+    }
+    /// Lists the arb files of `l10n_dir`, the template included.
+    pub fn arb_files(&self) -> Result<Vec<PathBuf>, String> {
+        let mut files: Vec<PathBuf> = stringe(
+            "could not list the arb directory",
+            std::fs::read_dir(self.root_dir.join(&self.l10n_dir)),
+        )?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "arb"))
+        .collect();
+        files.sort();
+        Ok(files)
+    }
+    /// Lists every source dart file under `lib/`, recursively and honoring `.gitignore`.
+    pub fn dart_files(&self) -> Vec<PathBuf> {
+        ignore::WalkBuilder::new(self.root_dir.join("lib"))
+            .require_git(false)
+            .build()
+            .flatten()
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+            .map(|e| e.into_path())
+            .filter(|p| self.is_source_file(p))
+            .collect()
+    }
     pub fn load() -> Result<Self, String> {
         let root = stringe("could not get current directory", std::env::current_dir())?;
 
@@ -49,6 +319,32 @@ impl Project {
                 .as_slice(),
             ),
         )?;
+        let config_path = root.join("arb-util.yaml");
+        let arb_util_config: Config = if config_path.exists() {
+            stringe(
+                "could not parse arb-util.yaml",
+                serde_yaml::from_slice(
+                    stringe("could not read arb-util.yaml", std::fs::read(config_path))?.as_slice(),
+                ),
+            )?
+        } else {
+            Config::default()
+        };
+
+        // without `{args}` messages with placeholders would become tear-offs of their getter
+        for template in [
+            &arb_util_config.call_site,
+            &arb_util_config.context_free_call_site,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !template.contains("{key}") || !template.contains("{args}") {
+                return Err(format!(
+                    "the call-site template {template:?} in arb-util.yaml must contain `{{key}}` and `{{args}}`"
+                ));
+            }
+        }
         if !config.arb_dir.starts_with("lib/") {
             return Err(String::from(
                 "Please, make sure your configuration arb-dir points to `lib/...`",
@@ -62,17 +358,29 @@ impl Project {
         //     ));
         // }
 
+        let l10n_dir: PathBuf = match config.arb_dir.strip_suffix("/") {
+            // remove possible end slash
+            Some(s) => s,
+            None => config.arb_dir.as_str(),
+        }
+        .into();
+
         Ok(Self {
-            root_dir: root,
-            l10n_dir: match config.arb_dir.strip_suffix("/") {
-                // remove possible end slash
-                Some(s) => s,
-                None => config.arb_dir.as_str(),
-            }
-            .into(),
+            output_dir: config
+                .output_dir
+                .map_or_else(|| l10n_dir.clone(), PathBuf::from),
+            l10n_dir,
             arb_template: config.template_arb_file,
             name: pubspec.name,
             localizations_file: config.output_localization_file,
+            output_class: config
+                .output_class
+                .unwrap_or_else(|| String::from("AppLocalizations")),
+            nullable_getter: config.nullable_getter.unwrap_or(true),
+            use_escaping: config.use_escaping.unwrap_or(false),
+            config: arb_util_config,
+            journal: Journal::new(&root),
+            root_dir: root,
         })
     }
 }
@@ -0,0 +1,67 @@
+use crate::arb::ArbFile;
+use crate::callsite::find_call_sites;
+use crate::project::Project;
+use crate::utils::{dart_identifier, stringe};
+
+/// `arb-util rename-key`, renames `old` to `new` in every arb file, metadata included,
+/// and at every dart call site. Nothing is written unless every file could be read.
+pub fn rename_key(p: &Project, old: &str, new: &str) -> Result<(), String> {
+    if dart_identifier(new).as_deref() != Some(new) {
+        return Err(format!(
+            "`{new}` is not a valid key, it must be an ASCII dart identifier starting with a \
+             lowercase letter, and not a reserved word"
+        ));
+    }
+    let arbs: Vec<ArbFile> = p
+        .arb_files()?
+        .into_iter()
+        .map(|path| p.arb_file(path))
+        .collect();
+    let mut contents = Vec::new();
+    for arb in &arbs {
+        let content = arb.read()?;
+        if content.messages.contains_key(new) || content.metadata.contains_key(new) {
+            return Err(format!("`{new}` already exists in {}", arb.path.display()));
+        }
+        contents.push(content);
+    }
+    let template = p.arb_file(p.arb_template_path()).read()?;
+    if !template.messages.contains_key(old) {
+        return Err(format!("no key `{old}` in the template arb file"));
+    }
+
+    let mut dart_changes = Vec::new();
+    for file in p.dart_files() {
+        let display = file.strip_prefix(&p.root_dir).unwrap_or(&file).display();
+        let mut content = stringe(
+            format!("could not read {display}").as_str(),
+            std::fs::read_to_string(&file),
+        )?;
+        let sites = find_call_sites(p, &content).map_err(|e| format!("{display}: {e}"))?;
+        let mut renamed = 0;
+        for site in sites.iter().rev().filter(|s| s.key == old) {
+            content.replace_range(site.key_span.clone(), new);
+            renamed += 1;
+        }
+        if renamed > 0 {
+            dart_changes.push((file, content, renamed));
+        }
+    }
+
+    for (arb, mut content) in arbs.iter().zip(contents) {
+        if !content.messages.contains_key(old) && !content.metadata.contains_key(old) {
+            continue;
+        }
+        content.rename(old, new);
+        arb.write(&content)?;
+        println!("[rename] Updated {}", arb.path.display());
+    }
+    for (file, content, renamed) in dart_changes {
+        p.journal.write(&file, &content)?;
+        println!(
+            "[rename] Renamed {renamed} call sites in {}",
+            file.strip_prefix(&p.root_dir).unwrap_or(&file).display()
+        );
+    }
+    Ok(())
+}
@@ -1,13 +1,12 @@
 use std::process::Stdio;
 
-use crate::{arb::ArbFile, project::Project, watcher::DirWatcher};
-use serde_json::Value;
+use crate::{project::Project, watcher::DirWatcher};
 use tokio::time::sleep;
 
 /// Synchronizes keys from the template ARB file to all other ARB files in the directory.
 async fn sync_keys(project: &Project) -> Result<(), String> {
     let template_path = project.arb_template_path();
-    let template_arb = ArbFile::new(template_path.clone());
+    let template_arb = project.arb_file(template_path.clone());
     let template = template_arb.read()?;
 
     let l10n_dir = project.root_dir.join(&project.l10n_dir);
@@ -26,27 +25,19 @@ async fn sync_keys(project: &Project) -> Result<(), String> {
             }
 
             println!("[syncer] Checking file: {:?}", path.file_name().unwrap());
-            let other_arb = ArbFile::new(path);
-            let mut other_content = other_arb.read()?;
-
-            let mut changed = false;
-            for &key in &template
-                .keys()
-                .filter(|k| !k.starts_with('@'))
-                .collect::<Vec<_>>()
-            {
-                if !other_content.contains_key(key) {
-                    let template_value = template.get(key).unwrap().as_str().unwrap_or("");
-                    let placeholder = format!("#{}", template_value);
-                    println!("  -> Adding missing key '{}' with placeholder", key);
-                    other_content.insert(key.clone(), Value::String(placeholder));
-                    changed = true;
+            let other_arb = project.arb_file(path);
+            other_arb.update(|other_content| {
+                let mut changed = false;
+                for (key, template_value) in &template.messages {
+                    if !other_content.messages.contains_key(key) {
+                        let placeholder = format!("#{}", template_value);
+                        println!("  -> Adding missing key '{}' with placeholder", key);
+                        other_content.messages.insert(key.clone(), placeholder);
+                        changed = true;
+                    }
                 }
-            }
-
-            if changed {
-                other_arb.write(&other_content)?;
-            }
+                Ok(changed)
+            })?;
         }
     }
     flutter_gen().await;
@@ -68,12 +59,20 @@ pub async fn flutter_gen() {
     }
 }
 
-pub async fn run(p: Project) -> Result<(), String> {
+pub async fn run(mut p: Project) -> Result<(), String> {
     println!("[syncer] Started. Making initial sync.");
-    let mut watcher = DirWatcher::new(&p.arb_template_path(), true)?;
-    while watcher.next().await.is_some() {
+    let template_path = p.arb_template_path();
+    // the directory, as writes rename a new file over the template and a watch on the
+    // template itself would stay on the old one
+    let mut watcher = DirWatcher::new(&p.root_dir.join(&p.l10n_dir), true)?;
+    while let Some(path) = watcher.next().await {
+        // the initial run yields an empty path
+        if !path.as_os_str().is_empty() && path != template_path {
+            continue;
+        }
         sleep(std::time::Duration::from_millis(500)).await;
         println!("[syncer] Template ARB file changed. Re-running sync...");
+        p.journal.new_run();
         if let Err(e) = sync_keys(&p).await {
             println!("[syncer] Error during sync: {}", e);
         }
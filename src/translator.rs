@@ -1,7 +1,9 @@
-use super::{arb::ArbFile, project::Project, watcher::DirWatcher};
+use super::{arb::ArbFile, icu, project::Project, watcher::DirWatcher};
 use reqwest::Client;
 use serde_json::{Value, json};
+use std::collections::BTreeMap;
 use std::env;
+use std::path::PathBuf;
 use tokio::{sync::mpsc::channel, time::sleep};
 
 #[derive(Debug)]
@@ -9,13 +11,42 @@ struct TranslationJob {
     key: String,
     text: String,
     lang: String,
+    /// The `description` of the key in the template, context for the translation.
+    description: Option<String>,
+    /// The arguments of the template message, the translation must have the same.
+    arguments: Vec<String>,
     arb_file: ArbFile,
 }
 
+impl TranslationJob {
+    /// Tells why `translation` can't replace the message, if it can't.
+    fn check(&self, translation: &str, escaping: bool) -> Option<String> {
+        let message = match icu::parse(translation, escaping) {
+            Ok(message) => message,
+            Err(e) => return Some(e.to_string()),
+        };
+        let mut arguments = icu::arguments(&message);
+        let mut expected = self.arguments.clone();
+        arguments.sort();
+        expected.sort();
+        (arguments != expected)
+            .then(|| format!("it has the arguments {arguments:?} instead of {expected:?}"))
+    }
+}
+
 fn find_untranslated_strings(project: &Project) -> Result<Vec<TranslationJob>, String> {
     let mut jobs = Vec::new();
     let l10n_dir = project.root_dir.join(&project.l10n_dir);
     let template_path = project.arb_template_path();
+    // the descriptions and arguments come from the template, without it the pass waits for
+    // the next change, a template mid-edit is no reason to stop translating for good
+    let template = match project.arb_file(template_path.clone()).read() {
+        Ok(template) => template,
+        Err(e) => {
+            println!("[translator] Error reading the template, skipping this pass: {e}");
+            return Ok(jobs);
+        }
+    };
 
     for entry in std::fs::read_dir(l10n_dir)
         .map_err(|e| e.to_string())?
@@ -26,31 +57,42 @@ fn find_untranslated_strings(project: &Project) -> Result<Vec<TranslationJob>, S
             continue;
         }
 
-        let lang = path
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .split('_')
-            .next_back()
-            .unwrap_or_default();
-
-        if lang.is_empty() {
-            continue;
-        }
-
-        let arb_file = ArbFile::new(path.clone());
+        let arb_file = project.arb_file(path.clone());
         match arb_file.read() {
-            Ok(obj) => {
-                for (key, value) in obj {
-                    if let Some(s_val) = value.as_str()
-                        && s_val.starts_with('#')
-                    {
+            Ok(document) => {
+                // `@@locale` knows better than the file name, `app_pt_BR.arb` is `pt_BR`
+                let lang = match document.locale() {
+                    Some(locale) => locale.to_string(),
+                    None => path
+                        .file_stem()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .split('_')
+                        .next_back()
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                if lang.is_empty() {
+                    continue;
+                }
+                for (key, value) in document.messages {
+                    if let Some(text) = value.strip_prefix('#') {
+                        let description = template
+                            .metadata
+                            .get(&key)
+                            .and_then(|m| m.description.clone());
+                        let arguments = match template.parse_message(&key, project.use_escaping) {
+                            Some(Ok(message)) => icu::arguments(&message),
+                            _ => Vec::new(),
+                        };
                         jobs.push(TranslationJob {
                             key: key.clone(),
-                            text: s_val.strip_prefix('#').unwrap().to_string(),
-                            lang: lang.to_string(),
-                            arb_file: ArbFile::new(arb_file.path.clone()),
+                            text: text.to_string(),
+                            lang: lang.clone(),
+                            description,
+                            arguments,
+                            arb_file: arb_file.clone(),
                         });
                     }
                 }
@@ -61,7 +103,7 @@ fn find_untranslated_strings(project: &Project) -> Result<Vec<TranslationJob>, S
     Ok(jobs)
 }
 
-pub async fn run(p: Project) -> Result<(), String> {
+pub async fn run(mut p: Project) -> Result<(), String> {
     let api_key = match env::var("GEMINI_API_KEY") {
         Ok(k) => k,
         Err(e) => {
@@ -81,6 +123,7 @@ pub async fn run(p: Project) -> Result<(), String> {
     while watcher.next().await.is_some() {
         sleep(std::time::Duration::from_millis(10000)).await;
 
+        p.journal.new_run();
         let jobs = find_untranslated_strings(&p)?;
         if jobs.is_empty() {
             continue;
@@ -94,13 +137,31 @@ pub async fn run(p: Project) -> Result<(), String> {
         let (tx, mut rx) = channel::<(TranslationJob, String)>(100);
 
         let writer_handle = tokio::spawn(async move {
-            let mut count = 0;
+            // each arb file is written once for the whole batch, and journaled once
+            let mut translations: BTreeMap<PathBuf, (ArbFile, Vec<(String, String)>)> =
+                BTreeMap::new();
             while let Some((job, translated_text)) = rx.recv().await {
-                match job.arb_file.add_key(&job.key, &translated_text) {
-                    Ok(_) => count += 1,
+                translations
+                    .entry(job.arb_file.path.clone())
+                    .or_insert_with(|| (job.arb_file, Vec::new()))
+                    .1
+                    .push((job.key, translated_text));
+            }
+            let mut count = 0;
+            for (arb_file, keys) in translations.into_values() {
+                let written = arb_file.update(|document| {
+                    for (key, text) in &keys {
+                        document.messages.insert(key.clone(), text.clone());
+                    }
+                    Ok(true)
+                });
+                match written {
+                    Ok(_) => count += keys.len(),
                     Err(e) => println!(
-                        "  [translator] ERROR: Failed to write key '{}': {}",
-                        job.key, e
+                        "  [translator] ERROR: Failed to write {} translations to {}: {}",
+                        keys.len(),
+                        arb_file.path.display(),
+                        e
                     ),
                 }
             }
@@ -113,11 +174,19 @@ pub async fn run(p: Project) -> Result<(), String> {
             let api_key = api_key.clone();
             let tx = tx.clone();
             let rate_tx = rate_tx.clone();
+            let escaping = p.use_escaping;
 
             tokio::spawn(async move {
                 println!("[translator] Translating '{}' to {}", job.key, job.lang);
-                match translate(&api_key, &job.text, &job.lang).await {
+                match translate(&api_key, &job.text, &job.lang, job.description.as_deref()).await {
                     TranslateResult::Translated(translated_text) => {
+                        if let Some(problem) = job.check(&translated_text, escaping) {
+                            println!(
+                                "  [translator] ERROR: Dropped the translation of '{}' to {}, {}",
+                                job.key, job.lang, problem
+                            );
+                            return;
+                        }
                         if tx.send((job, translated_text)).await.is_err() {
                             eprintln!("[translator] Failed to send result to writer");
                         }
@@ -158,7 +227,12 @@ pub enum TranslateResult {
     Error(String),
     RateLimitExceeded,
 }
-pub async fn translate(api_key: &str, txt: &str, lang: &str) -> TranslateResult {
+pub async fn translate(
+    api_key: &str,
+    txt: &str,
+    lang: &str,
+    description: Option<&str>,
+) -> TranslateResult {
     // Retrieve the Gemini API key from environment variables
 
     let client = Client::new();
@@ -166,7 +240,7 @@ pub async fn translate(api_key: &str, txt: &str, lang: &str) -> TranslateResult
     let model_name = "gemini-2.5-flash-lite";
 
     // Improved system prompt for nuanced translation
-    let system_prompt = format!(
+    let mut system_prompt = format!(
         "You are a highly skilled and nuanced language translation AI. Your task is to accurately and idiomatically translate the provided text into {}.
         1. Source Language Detection: Automatically detect the source language of the input text.
         2. Context and Nuance: Preserve the original meaning, tone, and cultural nuances of the text as much as possible.
@@ -176,6 +250,12 @@ formatting. Ensure the output is clean and ready for direct use.
 ",
         lang, "{}"
     );
+    // short ui words like "Back" or "Open" are ambiguous without it
+    if let Some(description) = description {
+        system_prompt.push_str(&format!(
+            "        5. Context: the text is used in an app user interface as: {description}\n"
+        ));
+    }
 
     // Construct the JSON request body
     let request_body = json!({
@@ -0,0 +1,39 @@
+use std::collections::BTreeSet;
+
+use crate::callsite::referenced_keys;
+use crate::project::Project;
+
+/// `arb-util unused`, reports the template keys no dart file references through the
+/// call-site templates. With `prune` they are removed, with their metadata, from every
+/// arb file. Returns whether unused keys were left in place.
+pub fn unused(p: &Project, prune: bool) -> Result<bool, String> {
+    let template = p.arb_file(p.arb_template_path()).read()?;
+    let referenced = referenced_keys(p)?;
+    let unused: BTreeSet<String> = template
+        .messages
+        .keys()
+        .filter(|k| !referenced.contains(*k))
+        .cloned()
+        .collect();
+    if unused.is_empty() {
+        println!("[unused] Every key is used");
+        return Ok(false);
+    }
+    for key in &unused {
+        println!("[unused] {key}: {:?}", template.messages[key]);
+    }
+    if !prune {
+        return Ok(true);
+    }
+    for path in p.arb_files()? {
+        let arb = p.arb_file(path);
+        let removed = arb.remove_keys(&unused)?;
+        if removed > 0 {
+            println!(
+                "[unused] Removed {removed} entries from {}",
+                arb.path.display()
+            );
+        }
+    }
+    Ok(false)
+}
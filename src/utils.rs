@@ -5,10 +5,229 @@ pub fn stringe<T, K: std::error::Error>(msg: &str, val: Result<T, K>) -> Result<
     val.map_err(|e| String::from(msg) + ": " + e.to_string().as_str())
 }
 
-pub fn localization_getter(n: &String) -> String {
-    format!("AppLocalizations.of(context)!.{n}")
+/// Fills a call-site template such as `{class}.of(context)!.{key}{args}`,
+/// `{args}` becomes `(a, b)` when the message has placeholders and nothing otherwise.
+pub fn localization_call(template: &str, class: &str, n: &str, args: &[String]) -> String {
+    let args = if args.is_empty() {
+        String::new()
+    } else {
+        format!("({})", args.join(", "))
+    };
+    template
+        .replace("{class}", class)
+        .replace("{key}", n)
+        .replace("{args}", &args)
 }
 
+/// Names an ICU placeholder after the interpolated dart expression,
+/// `cart.total` gives `cartTotal`, and `arg{index}` is used when nothing usable is left.
+pub fn placeholder_name(expr: &str, index: usize) -> String {
+    let mut out = String::new();
+    for word in expr
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        if out.is_empty() {
+            out.push_str(word);
+        } else {
+            let mut chars = word.chars();
+            if let Some(first_char) = chars.next() {
+                out.extend(first_char.to_uppercase());
+                out.push_str(chars.as_str());
+            }
+        }
+    }
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("arg{index}")
+    } else {
+        out
+    }
+}
+
+/// Returns `name`, or `name` with the first free numeric suffix if it is already `taken`.
+pub fn unique_name(name: &str, taken: &[String]) -> String {
+    if !taken.iter().any(|t| t == name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|i| format!("{name}{i}"))
+        .find(|n| !taken.contains(n))
+        .unwrap()
+}
+
+/// Dart's reserved words and built-in identifiers, and the static members of the class
+/// `flutter gen-l10n` generates, none of which a message getter can be named.
+const RESERVED: &[&str] = &[
+    "abstract",
+    "as",
+    "assert",
+    "async",
+    "await",
+    "base",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "covariant",
+    "default",
+    "deferred",
+    "do",
+    "dynamic",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "extension",
+    "external",
+    "factory",
+    "false",
+    "final",
+    "finally",
+    "for",
+    "get",
+    "hide",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "interface",
+    "is",
+    "late",
+    "library",
+    "mixin",
+    "new",
+    "null",
+    "of",
+    "on",
+    "operator",
+    "part",
+    "required",
+    "rethrow",
+    "return",
+    "sealed",
+    "set",
+    "show",
+    "static",
+    "super",
+    "switch",
+    "sync",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "var",
+    "void",
+    "when",
+    "while",
+    "with",
+    "yield",
+    "delegate",
+    "localeName",
+    "localizationsDelegates",
+    "supportedLocales",
+];
+
+/// Makes `key` a getter name `flutter gen-l10n` accepts: an ASCII dart identifier starting
+/// with a lowercase letter, `3LittlePigs` gives `key3LittlePigs` and `continue` gives
+/// `continueText`. None when it has characters no identifier can hold, non-ASCII ones included.
+pub fn dart_identifier(key: &str) -> Option<String> {
+    // a leading `_` would make the getter private
+    let key = key.trim_start_matches('_');
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    let mut out = String::new();
+    let mut chars = key.chars();
+    let first_char = chars.next().unwrap();
+    if first_char.is_ascii_digit() {
+        out.push_str("key");
+        out.push(first_char);
+    } else {
+        out.push(first_char.to_ascii_lowercase());
+    }
+    out.push_str(chars.as_str());
+    if RESERVED.contains(&out.as_str()) {
+        out.push_str("Text");
+    }
+    Some(out)
+}
+
+/// Replaces non-ASCII characters with their closest ASCII spelling, when there is one.
+pub fn transliterate(s: &str) -> String {
+    s.chars()
+        .map(|c| match c.is_ascii() {
+            true => c.to_string(),
+            false => deunicode::deunicode_char(c)
+                .filter(|t| !t.is_empty())
+                .map_or_else(|| c.to_string(), |t| t.to_string()),
+        })
+        .collect()
+}
+
+/// camelCases the alphanumeric words of `s`, dropping everything else
+/// and keeping only as many words as fit in `max_length`.
+pub fn slug(s: &str, max_length: usize) -> String {
+    let mut out = String::new();
+    for word in s
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let word = if out.is_empty() {
+            word.to_lowercase()
+        } else {
+            let mut chars = word.chars();
+            let first_char = chars.next().unwrap();
+            first_char
+                .to_uppercase()
+                .chain(chars.as_str().to_lowercase().chars())
+                .collect()
+        };
+        if out.len() + word.len() > max_length {
+            if out.is_empty() {
+                // a single huge word, cut it at a character boundary
+                out.extend(word.chars().take_while({
+                    let mut len = 0;
+                    move |c| {
+                        len += c.len_utf8();
+                        len <= max_length
+                    }
+                }));
+            }
+            break;
+        }
+        out.push_str(&word);
+    }
+    out
+}
+
+/// A short hash of `s` which stays the same across runs and rust versions (FNV-1a).
+pub fn short_hash(s: &str) -> String {
+    let hash = s.bytes().fold(0x811c9dc5u32, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    });
+    format!("msg{hash:08x}")
+}
+
+/// lowerCamelCases an identifier or file name: `LoginPage` or `login_page` give `loginPage`.
+pub fn lower_camel(s: &str) -> String {
+    let mut out = String::new();
+    for word in s.split('_').filter(|w| !w.is_empty()) {
+        let mut chars = word.chars();
+        let first_char = chars.next().unwrap();
+        if out.is_empty() {
+            out.extend(first_char.to_lowercase());
+        } else {
+            out.extend(first_char.to_uppercase());
+        }
+        out.push_str(chars.as_str());
+    }
+    out
+}
+
+/// The whole of `s` in camelCase, punctuation spelled out: `Save!` is `saveExclamationMark`.
 pub fn id_string(s: &str) -> String {
     let mut temp = String::new();
 
@@ -19,37 +238,37 @@ pub fn id_string(s: &str) -> String {
             | '}' | '<' | '>' | '/' | '\\' | '|' | '#' | '@' | '&' | '%' | '$' | '^' | '+'
             | '=' | '~' | '`' | '"' => {
                 let word = match c {
-                    '*' => "_asterix_",
-                    '\'' => "_apostrophe_",
-                    '-' => "_hyphen_",
-                    '!' => "_exclamation_mark_",
-                    '?' => "_question_mark_",
-                    '.' => "_period_",
-                    ',' => "_comma_",
-                    ':' => "_colon_",
-                    ';' => "_semicolon_",
-                    '(' => "_open_parenthesis_",
-                    ')' => "_close_parenthesis_",
-                    '[' => "_open_bracket_",
-                    ']' => "_close_bracket_",
-                    '{' => "_open_brace_",
-                    '}' => "_close_brace_",
-                    '<' => "_less_than_",
-                    '>' => "_greater_than_",
-                    '/' => "_forward_slash_",
-                    '\\' => "_backslash_",
-                    '|' => "_pipe_",
-                    '#' => "_hash_",
-                    '@' => "_at_",
-                    '&' => "_ampersand_",
-                    '%' => "_percent_",
-                    '$' => "_dollar_",
-                    '^' => "_caret_",
-                    '+' => "_plus_",
-                    '=' => "_equals_",
-                    '~' => "_tilde_",
-                    '`' => "_backtick_",
-                    '"' => "_double_quote_",
+                    '*' => "asterix",
+                    '\'' => "apostrophe",
+                    '-' => "hyphen",
+                    '!' => "exclamation mark",
+                    '?' => "question mark",
+                    '.' => "period",
+                    ',' => "comma",
+                    ':' => "colon",
+                    ';' => "semicolon",
+                    '(' => "open parenthesis",
+                    ')' => "close parenthesis",
+                    '[' => "open bracket",
+                    ']' => "close bracket",
+                    '{' => "open brace",
+                    '}' => "close brace",
+                    '<' => "less than",
+                    '>' => "greater than",
+                    '/' => "forward slash",
+                    '\\' => "backslash",
+                    '|' => "pipe",
+                    '#' => "hash",
+                    '@' => "at",
+                    '&' => "ampersand",
+                    '%' => "percent",
+                    '$' => "dollar",
+                    '^' => "caret",
+                    '+' => "plus",
+                    '=' => "equals",
+                    '~' => "tilde",
+                    '`' => "backtick",
+                    '"' => "double quote",
                     _ => unreachable!(),
                 };
                 if !temp.is_empty() && !temp.ends_with(' ') {
@@ -97,3 +316,32 @@ pub fn id_string(s: &str) -> String {
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentence_keys() {
+        assert_eq!(id_string("Save!"), "saveExclamationMark");
+        assert_eq!(id_string("Are you sure?"), "areYouSureQuestionMark");
+        assert_eq!(id_string("Loading..."), "loadingPeriodPeriodPeriod");
+        assert_eq!(id_string("Hi, name"), "hiCommaName");
+        assert_eq!(id_string(""), "");
+    }
+
+    #[test]
+    fn dart_identifiers() {
+        assert_eq!(dart_identifier("signIn").as_deref(), Some("signIn"));
+        assert_eq!(
+            dart_identifier("3LittlePigs").as_deref(),
+            Some("key3LittlePigs")
+        );
+        assert_eq!(dart_identifier("continue").as_deref(), Some("continueText"));
+        assert_eq!(dart_identifier("Of").as_deref(), Some("ofText"));
+        assert_eq!(dart_identifier("_hash_Tag").as_deref(), Some("hash_Tag"));
+        assert_eq!(dart_identifier("caféDéjàVu"), None);
+        assert_eq!(dart_identifier("γειά"), None);
+        assert_eq!(dart_identifier("__"), None);
+    }
+}